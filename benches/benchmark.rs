@@ -10,7 +10,7 @@ use std::io;
 
 use test::Bencher;
 
-use brainfuck::{precompile, Instruction, OptimizationLevel};
+use brainfuck::{precompile, Instruction, OptimizationLevel, Control};
 
 lazy_static! {
     // This program is trivial to run in both size and speed
@@ -27,93 +27,93 @@ lazy_static! {
 
 fn interpret(program: Vec<Instruction>) {
     let mut inp: &[u8] = &[];
-    brainfuck::interpret(&mut inp, io::sink(), program, |_| {});
+    brainfuck::interpret(&mut inp, io::sink(), program, |_| Control::Continue).unwrap();
 }
 
 #[bench]
 fn b01_compile_trivial(b: &mut Bencher) {
-    b.iter(|| precompile(TRIVIAL_SOURCE.iter(), OptimizationLevel::Off));
+    b.iter(|| precompile(TRIVIAL_SOURCE.iter(), OptimizationLevel::Off).unwrap());
 }
 
 #[bench]
 fn b01_compile_trivial_opt(b: &mut Bencher) {
-    b.iter(|| precompile(TRIVIAL_SOURCE.iter(), OptimizationLevel::Speed));
+    b.iter(|| precompile(TRIVIAL_SOURCE.iter(), OptimizationLevel::Speed).unwrap());
 }
 
 #[bench]
 fn b02_compile_large(b: &mut Bencher) {
-    b.iter(|| precompile(LARGE_SOURCE.iter(), OptimizationLevel::Off));
+    b.iter(|| precompile(LARGE_SOURCE.iter(), OptimizationLevel::Off).unwrap());
 }
 
 #[bench]
 fn b02_compile_large_opt(b: &mut Bencher) {
-    b.iter(|| precompile(LARGE_SOURCE.iter(), OptimizationLevel::Speed));
+    b.iter(|| precompile(LARGE_SOURCE.iter(), OptimizationLevel::Speed).unwrap());
 }
 
 #[bench]
 fn b03_compile_huge(b: &mut Bencher) {
-    b.iter(|| precompile(HUGE_SOURCE.iter(), OptimizationLevel::Off));
+    b.iter(|| precompile(HUGE_SOURCE.iter(), OptimizationLevel::Off).unwrap());
 }
 
 #[bench]
 fn b03_compile_huge_opt(b: &mut Bencher) {
-    b.iter(|| precompile(HUGE_SOURCE.iter(), OptimizationLevel::Speed));
+    b.iter(|| precompile(HUGE_SOURCE.iter(), OptimizationLevel::Speed).unwrap());
 }
 
 #[bench]
 fn b04_compile_simple(b: &mut Bencher) {
-    b.iter(|| precompile(SIMPLE_SOURCE.iter(), OptimizationLevel::Off));
+    b.iter(|| precompile(SIMPLE_SOURCE.iter(), OptimizationLevel::Off).unwrap());
 }
 
 #[bench]
 fn b04_compile_simple_opt(b: &mut Bencher) {
-    b.iter(|| precompile(SIMPLE_SOURCE.iter(), OptimizationLevel::Speed));
+    b.iter(|| precompile(SIMPLE_SOURCE.iter(), OptimizationLevel::Speed).unwrap());
 }
 
 #[bench]
 fn b05_compile_slow(b: &mut Bencher) {
-    b.iter(|| precompile(SLOW_SOURCE.iter(), OptimizationLevel::Off));
+    b.iter(|| precompile(SLOW_SOURCE.iter(), OptimizationLevel::Off).unwrap());
 }
 
 #[bench]
 fn b05_compile_slow_opt(b: &mut Bencher) {
-    b.iter(|| precompile(SLOW_SOURCE.iter(), OptimizationLevel::Speed));
+    b.iter(|| precompile(SLOW_SOURCE.iter(), OptimizationLevel::Speed).unwrap());
 }
 
 #[bench]
 fn b06_interpret_trivial(b: &mut Bencher) {
-    let program = precompile(TRIVIAL_SOURCE.iter(), OptimizationLevel::Off);
+    let program = precompile(TRIVIAL_SOURCE.iter(), OptimizationLevel::Off).unwrap();
     b.iter(|| interpret(program.clone()));
 }
 
 #[bench]
 fn b06_interpret_trivial_opt(b: &mut Bencher) {
-    let program = precompile(TRIVIAL_SOURCE.iter(), OptimizationLevel::Speed);
+    let program = precompile(TRIVIAL_SOURCE.iter(), OptimizationLevel::Speed).unwrap();
     b.iter(|| interpret(program.clone()));
 }
 
 #[bench]
 fn b07_interpret_simple(b: &mut Bencher) {
-    let program = precompile(SIMPLE_SOURCE.iter(), OptimizationLevel::Off);
+    let program = precompile(SIMPLE_SOURCE.iter(), OptimizationLevel::Off).unwrap();
     b.iter(|| interpret(program.clone()));
 }
 
 #[bench]
 fn b07_interpret_simple_opt(b: &mut Bencher) {
-    let program = precompile(SIMPLE_SOURCE.iter(), OptimizationLevel::Speed);
+    let program = precompile(SIMPLE_SOURCE.iter(), OptimizationLevel::Speed).unwrap();
     b.iter(|| interpret(program.clone()));
 }
 
 #[bench]
 #[ignore]
 fn b08_interpret_slow(b: &mut Bencher) {
-    let program = precompile(SLOW_SOURCE.iter(), OptimizationLevel::Off);
+    let program = precompile(SLOW_SOURCE.iter(), OptimizationLevel::Off).unwrap();
     b.iter(|| interpret(program.clone()));
 }
 
 #[bench]
 #[ignore]
 fn b08_interpret_slow_opt(b: &mut Bencher) {
-    let program = precompile(SLOW_SOURCE.iter(), OptimizationLevel::Speed);
+    let program = precompile(SLOW_SOURCE.iter(), OptimizationLevel::Speed).unwrap();
     b.iter(|| interpret(program.clone()));
 }
@@ -7,6 +7,7 @@ extern crate colored;
 extern crate brainfuck;
 
 use std::path::{Path};
+use std::process;
 use std::fs::File;
 use std::io;
 use std::io::prelude::*;
@@ -14,30 +15,64 @@ use std::thread;
 use std::time::Duration;
 
 use colored::*;
-use clap::{Arg, App};
+use clap::{Arg, App, AppSettings, ArgMatches, SubCommand};
 
-use brainfuck::{precompile, interpret, InterpreterState, DebugFormat, Instruction, OptimizationLevel};
+use brainfuck::codegen;
+use brainfuck::{
+    precompile_with_source_map,
+    precompile_with_passes,
+    builtin_pass,
+    interpret_with_config,
+    InterpreterConfig,
+    InterpreterState,
+    Control,
+    EofPolicy,
+    TapeConfig,
+    ExecutionSummary,
+    Cell,
+    DebugFormat,
+    Instruction,
+    OptimizationLevel,
+    PassManager,
+    ParseError,
+    Bytecode,
+    minify,
+    format_source,
+    FormatOptions,
+    RuntimeError,
+    SourceMap,
+};
+
+// Exit codes used to report why the program could not finish running
+const EXIT_INPUT_ERROR: i32 = 2;
+const EXIT_OUTPUT_ERROR: i32 = 3;
+const EXIT_MISMATCHED_JUMP: i32 = 4;
+const EXIT_OUT_OF_BOUNDS: i32 = 5;
+const EXIT_LIMIT_EXCEEDED: i32 = 6;
 
 macro_rules! exit_with_error(
     ($($arg:tt)*) => { {
-        use std::process;
         eprintln!($($arg)*);
         process::exit(1);
     } }
 );
 
+#[cfg(feature = "jit")]
+use brainfuck::interpret_jit;
+
+#[cfg(not(feature = "jit"))]
+fn interpret_jit<I, O>(_: I, _: O, _: Vec<Instruction>) -> Result<brainfuck::ExecutionSummary, RuntimeError> {
+    exit_with_error!("This brainfuck was built without JIT support. Rebuild it with `--features jit` to use --jit.");
+}
+
 fn main() {
     let args = App::new(crate_name!())
         .version(crate_version!())
         .version_short("v")
         .author(crate_authors!())
         .about(crate_description!())
-        .arg(Arg::with_name("input-file")
-            .help("The brainfuck file to process. Should contain brainfuck instructions")
-            .value_name("file")
-            .takes_value(true)
-            .required(true)
-        )
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .arg(input_file_arg())
         .arg(Arg::with_name("debug-enabled")
             .short("D")
             .long("debug")
@@ -50,28 +85,155 @@ fn main() {
             .possible_values(&["text", "json"])
             .help("The format of the debugging output")
         )
-        .arg(Arg::with_name("optimize")
-            .short("O")
-            .long("optimize")
-            .value_name("opt-level")
-            // Do not optimize by default if debug is enabled
-            .default_value_if("debug-enabled", None, "0")
-            .default_value("1")
-            .possible_values(&["0", "1"])
-            .help("Optimize for execution speed")
+        .arg(optimize_arg()
+            // Do not optimize by default if debug is enabled or if optimizing would change how
+            // the program behaves at the ends of the tape
+            .default_value_ifs(&[
+                ("debug-enabled", None, "0"),
+                ("tape-boundary", Some("clamp"), "0"),
+            ])
+        )
+        .arg(passes_arg())
+        .arg(Arg::with_name("jit")
+            .long("jit")
+            .conflicts_with_all(&["debug-enabled", "delay"])
+            .help("Compiles the program to native machine code before running it (requires the `jit` feature)")
         )
         .arg(Arg::with_name("delay")
             .long("delay")
             .takes_value(true)
             .help("Delays execution of each instruction by this amount in ms")
         )
+        .arg(Arg::with_name("cell-size")
+            .long("cell-size")
+            .value_name("bits")
+            .default_value("8")
+            .possible_values(&["8", "16", "32"])
+            .help("The number of bits in each cell of the tape. Input and output are still one byte at a time.")
+        )
+        .arg(Arg::with_name("eof")
+            .long("eof")
+            .value_name("policy")
+            .default_value("zero")
+            .possible_values(&["zero", "unchanged", "max", "error"])
+            .help("What reading does once there is no more input: set the cell to zero, leave it unchanged, set it to its largest value or stop with an error")
+        )
+        .arg(Arg::with_name("tape-size")
+            .long("tape-size")
+            .value_name("cells")
+            .takes_value(true)
+            .help("The most cells the tape may have. A tape that grows stops the program if it needs more cells than this. Any other tape has exactly this many cells [default: no limit, or 30000 if the tape does not grow]")
+        )
+        .arg(Arg::with_name("tape-boundary")
+            .long("tape-boundary")
+            .value_name("policy")
+            .default_value("grow")
+            .possible_values(&["grow", "wrap", "error", "clamp"])
+            .help("What happens when the pointer moves past either end of the tape: add more cells, wrap around to the other end, stop with an error or stay at the end. Programs are not optimized on a tape that clamps the pointer.")
+        )
+        .arg(Arg::with_name("max-steps")
+            .long("max-steps")
+            .value_name("steps")
+            .takes_value(true)
+            .help("Stops the program if it runs more than this many instructions")
+        )
+        .arg(Arg::with_name("timeout")
+            .long("timeout")
+            .value_name("seconds")
+            .takes_value(true)
+            .help("Stops the program if it runs for longer than this many seconds (e.g. 2.5)")
+        )
+        .subcommand(SubCommand::with_name("build")
+            .about("Compiles a brainfuck program to another language or an executable instead of running it")
+            .arg(input_file_arg())
+            .arg(Arg::with_name("emit")
+                .long("emit")
+                .value_name("language")
+                .default_value("c")
+                .possible_values(&["c", "rust", "llvm", "wat", "asm", "elf", "bfc"])
+                .help("The language to compile the program to (llvm is textual LLVM IR, asm is x86-64 assembly for Linux, elf is a Linux x86-64 executable and bfc is bytecode that this interpreter can run directly). Defaults to bfc if the output file ends in .bfc")
+            )
+            .arg(Arg::with_name("strip")
+                .long("strip")
+                .help("Leave the source map out of bytecode, which makes it smaller but means it cannot be debugged")
+            )
+            .arg(Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("file")
+                .takes_value(true)
+                .help("Write the compiled program to this file instead of stdout")
+            )
+            .arg(Arg::with_name("tape-size")
+                .long("tape-size")
+                .value_name("cells")
+                .takes_value(true)
+                .help("The number of cells in the tape of the compiled program [default: 65536]")
+            )
+            .arg(optimize_arg())
+            .arg(passes_arg())
+        )
+        .subcommand(SubCommand::with_name("disasm")
+            .about("Prints the instructions that a brainfuck program is precompiled to")
+            .arg(input_file_arg())
+            .arg(Arg::with_name("format")
+                .long("format")
+                .value_name("format")
+                .default_value("text")
+                .possible_values(&["text", "json"])
+                .help("The format of the listing. The JSON format prints one object per instruction.")
+            )
+            .arg(optimize_arg())
+            .arg(passes_arg())
+        )
+        .subcommand(SubCommand::with_name("fmt")
+            .about("Rewrites the source of a brainfuck program with the body of each loop indented")
+            .arg(input_file_arg())
+            .arg(Arg::with_name("minify")
+                .long("minify")
+                .conflicts_with_all(&["width", "strip-comments", "annotate"])
+                .help("Strips comments and redundant instructions to make the source as short as possible instead of indenting it")
+            )
+            .arg(Arg::with_name("width")
+                .long("width")
+                .value_name("columns")
+                .takes_value(true)
+                .help("The maximum length of a line [default: 80]")
+            )
+            .arg(Arg::with_name("strip-comments")
+                .long("strip-comments")
+                .help("Removes comments instead of keeping them")
+            )
+            .arg(Arg::with_name("annotate")
+                .long("annotate")
+                .help("Follows each run of more than three of the same instruction with its length")
+            )
+            .arg(Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("file")
+                .takes_value(true)
+                .help("Write the source to this file instead of stdout")
+            )
+        )
         .get_matches();
 
-    let source_path = Path::new(args.value_of("input-file").unwrap());
-    if !source_path.exists() || !source_path.is_file() {
-        exit_with_error!("Not a valid file: '{}'", source_path.display());
+    if let Some(args) = args.subcommand_matches("build") {
+        build(args);
+        return;
+    }
+    if let Some(args) = args.subcommand_matches("disasm") {
+        disasm(args);
+        return;
+    }
+    if let Some(args) = args.subcommand_matches("fmt") {
+        fmt(args);
+        return;
     }
 
+    let source_path = Path::new(args.value_of("input-file").unwrap());
+    let bytes = read_source(source_path);
+
     let debug_mode = args.is_present("debug-enabled");
     // We can call unwrap() because the validation is already done by clap
     let debug_format = args.value_of("debug-format").unwrap().parse().unwrap();
@@ -82,57 +244,418 @@ fn main() {
         0
     };
 
-    // We can call unwrap() because the validation is already done by clap
-    let opt = args.value_of("optimize").unwrap().parse().unwrap();
+    let cell_size = args.value_of("cell-size").unwrap();
+    let mut tape = TapeConfig {
+        size: None,
+        boundary: args.value_of("tape-boundary").unwrap().parse().unwrap(),
+    };
+    if let Some(tape_size) = args.value_of("tape-size") {
+        tape.size = match tape_size.parse() {
+            Ok(0) => exit_with_error!("Invalid tape size: the tape must have at least one cell"),
+            Ok(tape_size) => Some(tape_size),
+            Err(e) => exit_with_error!("Invalid tape size: {}", e),
+        };
+    }
+    let max_steps = args.value_of("max-steps").map(|steps| {
+        steps.parse().unwrap_or_else(|e: std::num::ParseIntError| exit_with_error!("Invalid maximum number of steps: {}", e))
+    });
+    let timeout = args.value_of("timeout").map(|timeout| match timeout.parse() {
+        // Duration::from_secs_f64 panics on values that do not fit in a Duration
+        Ok(seconds) if seconds >= 0.0 && seconds < u64::MAX as f64 => Duration::from_secs_f64(seconds),
+        _ => exit_with_error!("Invalid timeout: expected a number of seconds"),
+    });
+    let config = InterpreterConfig {
+        eof: args.value_of("eof").unwrap().parse().unwrap(),
+        tape,
+        max_steps,
+        timeout,
+    };
 
-    let mut f = File::open(source_path).unwrap_or_else(|e| {
-        exit_with_error!("Could not open source file: {}", e);
+    let bytecode = load_program(&args, &bytes, source_path);
+    if !config.tape.boundary.allows_optimization() && bytecode.opt != Some(OptimizationLevel::Off) {
+        exit_with_error!("Optimized programs cannot run with --tape-boundary clamp. Use -O0 instead.");
+    }
+    if debug_mode && bytecode.source_map.is_none() {
+        exit_with_error!("Cannot debug '{}' because it was built with --strip", source_path.display());
+    }
+    let debug_format = if debug_mode { Some(debug_format) } else { None };
+
+    let result = if args.is_present("jit") {
+        if cell_size != "8" {
+            exit_with_error!("The JIT only supports 8-bit cells");
+        }
+        if config.eof != EofPolicy::Zero {
+            exit_with_error!("The JIT only supports setting the cell to zero at the end of the input");
+        }
+        if config.tape != TapeConfig::default() {
+            exit_with_error!("The JIT only supports a tape that grows without a limit");
+        }
+        if config.max_steps.is_some() || config.timeout.is_some() {
+            exit_with_error!("The JIT does not support --max-steps or --timeout");
+        }
+        interpret_jit(io::stdin(), io::stdout(), bytecode.program)
+    }
+    else {
+        match cell_size {
+            "8" => run::<u8>(bytecode, &config, debug_format, delay),
+            "16" => run::<u16>(bytecode, &config, debug_format, delay),
+            "32" => run::<u32>(bytecode, &config, debug_format, delay),
+            _ => unreachable!(),
+        }
+    };
+
+    if let Err(err) = result {
+        let code = match err {
+            // The reader of our output went away (e.g. `brainfuck x.bf | head`), so there is
+            // no one left to report the error to
+            RuntimeError::OutputError(ref err) if err.kind() == io::ErrorKind::BrokenPipe => {
+                process::exit(EXIT_OUTPUT_ERROR);
+            },
+            RuntimeError::OutputError(..) => EXIT_OUTPUT_ERROR,
+            RuntimeError::InputError(..) | RuntimeError::EndOfInput { .. } => EXIT_INPUT_ERROR,
+            RuntimeError::MismatchedJump { .. } => EXIT_MISMATCHED_JUMP,
+            RuntimeError::OutOfBounds { .. } => EXIT_OUT_OF_BOUNDS,
+            RuntimeError::LimitExceeded { .. } => EXIT_LIMIT_EXCEEDED,
+        };
+        eprintln!("{}: {}", "error".red().bold(), err);
+        process::exit(code);
+    }
+}
+
+fn input_file_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("input-file")
+        .help("The brainfuck file to process. Should contain brainfuck instructions")
+        .value_name("file")
+        .takes_value(true)
+        .required(true)
+}
+
+fn optimize_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("optimize")
+        .short("O")
+        .long("optimize")
+        .value_name("opt-level")
+        .default_value("1")
+        .possible_values(&["0", "1", "2", "3", "s"])
+        .help("The optimization level. Use \"s\" to produce the fewest instructions")
+}
+
+fn passes_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("passes")
+        .long("passes")
+        .value_name("passes")
+        .takes_value(true)
+        .help("Run exactly these comma-separated optimization passes instead of the passes for the optimization level (available passes: fold, clear, mul, scan, offset)")
+}
+
+/// Compiles the program to another language (the `build` subcommand)
+fn build(args: &ArgMatches) {
+    let source_path = Path::new(args.value_of("input-file").unwrap());
+    let bytes = read_source(source_path);
+    let mut bytecode = load_program(args, &bytes, source_path);
+    let program = &bytecode.program;
+
+    let mut options = codegen::Options::default();
+    if let Some(tape_size) = args.value_of("tape-size") {
+        options.tape_size = match tape_size.parse() {
+            Ok(0) => exit_with_error!("Invalid tape size: the tape must have at least one cell"),
+            Ok(tape_size) => tape_size,
+            Err(e) => exit_with_error!("Invalid tape size: {}", e),
+        };
+    }
+
+    let output_path = args.value_of("output").map(Path::new);
+    let emit = match output_path.and_then(Path::extension) {
+        Some(extension) if extension == "bfc" && args.occurrences_of("emit") == 0 => "bfc",
+        // We can call unwrap() because the validation is already done by clap
+        _ => args.value_of("emit").unwrap(),
+    };
+    let code = match emit {
+        "c" => codegen::c::emit(program, &options).map(String::into_bytes),
+        "rust" => codegen::rust::emit(program, &options).map(String::into_bytes),
+        "llvm" => codegen::llvm::emit(program, &options).map(String::into_bytes),
+        "wat" => codegen::wat::emit(program, &options).map(String::into_bytes),
+        "asm" => codegen::asm::emit(program, &options).map(String::into_bytes),
+        "elf" => codegen::elf::emit(program, &options),
+        "bfc" => {
+            if args.is_present("strip") {
+                bytecode.source_map = None;
+            }
+            Ok(bytecode.to_bytes())
+        },
+        _ => unreachable!(),
+    };
+    let code = code.unwrap_or_else(|err| {
+        exit_with_error!("Could not compile '{}': {}", source_path.display(), err)
     });
 
-    let mut bytes = Vec::new();
-    f.read_to_end(&mut bytes).expect("Fatal: Could not read source file");
-    let program = precompile(bytes.iter(), opt);
+    write_output(output_path, &code, emit == "elf");
+}
+
+/// Runs the program with the interpreter using cells of type C, printing debugging information
+/// in the given format if there is one
+fn run<C: Cell>(bytecode: Bytecode, config: &InterpreterConfig, debug_format: Option<DebugFormat>, delay: u64) -> Result<ExecutionSummary, RuntimeError> {
+    let Bytecode {program, opt, source_map} = bytecode;
 
     // Based on debug_mode and delay, this will run one of several functions
     // If there is no delay and debug mode is off, performance is prioritized and the interpreter
     // should run at top speed
     let input = io::stdin();
     let output = io::stdout();
-    if debug_mode {
+    if let Some(debug_format) = debug_format {
+        let source_map = source_map.expect("bug: debugging a program without a source map");
         match debug_format {
             DebugFormat::Text => {
-                interpret(input, output, program, |state| format_human_readable(state, delay, match opt {
-                    OptimizationLevel::Off => 1,
-                    OptimizationLevel::Speed => 4,
-                }));
+                interpret_with_config(input, output, program, config, |state: InterpreterState<C>| format_human_readable(state, &source_map, delay, match opt {
+                    Some(OptimizationLevel::Off) => 1,
+                    _ => 4,
+                }))
             },
 
-            DebugFormat::Json => interpret(input, output, program, |state| format_json(state, delay)),
+            DebugFormat::Json => interpret_with_config(input, output, program, config, |state: InterpreterState<C>| format_json(state, &source_map, delay)),
         }
     }
     // Need this condition because delay can be active without debug_mode
     else if delay > 0 {
-        interpret(input, output, program, |_| thread::sleep(Duration::from_millis(delay)));
+        interpret_with_config::<C, _, _, _>(input, output, program, config, |_| {
+            thread::sleep(Duration::from_millis(delay));
+            Control::Continue
+        })
+    }
+    else {
+        interpret_with_config::<C, _, _, _>(input, output, program, config, |_| Control::Continue)
+    }
+}
+
+/// Rewrites the source of a program (the `fmt` subcommand)
+fn fmt(args: &ArgMatches) {
+    let source_path = Path::new(args.value_of("input-file").unwrap());
+    let bytes = read_source(source_path);
+    if Bytecode::is_bytecode(&bytes) {
+        exit_with_error!("Cannot format '{}' because it is bytecode, not source", source_path.display());
+    }
+
+    let result = if args.is_present("minify") {
+        minify(bytes.iter()).map(|mut source| {
+            source.push('\n');
+            source
+        })
     }
     else {
-        interpret(input, output, program, |_| {});
+        let mut options = FormatOptions::default();
+        if let Some(width) = args.value_of("width") {
+            options.line_width = match width.parse() {
+                Ok(0) => exit_with_error!("Invalid width: lines must have at least one column"),
+                Ok(width) => width,
+                Err(e) => exit_with_error!("Invalid width: {}", e),
+            };
+        }
+        options.comments = !args.is_present("strip-comments");
+        options.annotate_runs = args.is_present("annotate");
+        format_source(bytes.iter(), &options)
+    };
+    let source = result.unwrap_or_else(|err| {
+        print_parse_error(&bytes, source_path, &err);
+        process::exit(1);
+    });
+    write_output(args.value_of("output").map(Path::new), source.as_bytes(), false);
+}
+
+/// Writes the data to the file or to stdout if there is no file, exiting if it cannot be
+/// written
+fn write_output(path: Option<&Path>, data: &[u8], executable: bool) {
+    let result = match path {
+        Some(path) => File::create(path).and_then(|mut f| {
+            f.write_all(data)?;
+            if executable {
+                make_executable(&f)?;
+            }
+            Ok(())
+        }),
+        None => io::stdout().write_all(data),
+    };
+    if let Err(err) = result {
+        eprintln!("{}: Could not write output: {}", "error".red().bold(), err);
+        process::exit(EXIT_OUTPUT_ERROR);
+    }
+}
+
+/// Prints every instruction of the program along with where it jumps to and the source it
+/// came from (the `disasm` subcommand)
+fn disasm(args: &ArgMatches) {
+    let source_path = Path::new(args.value_of("input-file").unwrap());
+    let bytes = read_source(source_path);
+    let Bytecode {program, source_map, ..} = load_program(args, &bytes, source_path);
+    // We can call unwrap() because the validation is already done by clap
+    let format = args.value_of("format").unwrap().parse().unwrap();
+
+    // For "[" this is the instruction after the matching "]" and for "]" the instruction after
+    // the matching "[", just like when the program runs
+    let mut targets = vec![None; program.len()];
+    for (index, &instr) in program.iter().enumerate() {
+        if let Instruction::JumpBackwardUnlessZero {matching} = instr {
+            targets[index] = Some(matching);
+            targets[matching - 1] = Some(index + 1);
+        }
+    }
+
+    let index_width = program.len().saturating_sub(1).to_string().len();
+    let instruction_width = program.iter().map(|instr| instr.to_string().len()).max().unwrap_or(0);
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let result = program.iter().enumerate().try_for_each(|(index, instr)| {
+        let span = source_map.as_ref().and_then(|source_map| source_map.get(index));
+        match format {
+            DebugFormat::Text => {
+                let target = match targets[index] {
+                    Some(target) => format!("-> {}", target),
+                    None => String::new(),
+                };
+                let location = match span {
+                    Some(span) => format!("{}:{}", span.start.line, span.start.column),
+                    None => String::new(),
+                };
+                let line = format!("{:>index_width$}  {:instruction_width$}  {:target_width$}  {}", index,
+                    instr.to_string(), target, location,
+                    index_width = index_width,
+                    instruction_width = instruction_width,
+                    target_width = index_width + 3);
+                writeln!(out, "{}", line.trim_end())
+            },
+            DebugFormat::Json => {
+                let target = targets[index].map_or("null".to_owned(), |target| target.to_string());
+                let location = match span {
+                    Some(span) => format!("\"sourceOffset\": {}, \"sourceLength\": {}, \"sourceLine\": {}, \"sourceColumn\": {}",
+                        span.start.offset, span.len, span.start.line, span.start.column),
+                    None => "\"sourceOffset\": null, \"sourceLength\": null, \"sourceLine\": null, \"sourceColumn\": null".to_owned(),
+                };
+                writeln!(out, "{{\"index\": {}, \"instruction\": \"{}\", \"target\": {}, {}}}",
+                    index, instr, target, location)
+            },
+        }
+    }).and_then(|_| out.flush());
+
+    match result {
+        Ok(()) => {},
+        // The reader of our output went away (e.g. `brainfuck disasm x.bf | head`)
+        Err(ref err) if err.kind() == io::ErrorKind::BrokenPipe => process::exit(EXIT_OUTPUT_ERROR),
+        Err(err) => {
+            eprintln!("{}: Could not write output: {}", "error".red().bold(), err);
+            process::exit(EXIT_OUTPUT_ERROR);
+        },
+    }
+}
+
+#[cfg(unix)]
+fn make_executable(file: &File) -> io::Result<()> {
+    use std::fs::Permissions;
+    use std::os::unix::fs::PermissionsExt;
+    file.set_permissions(Permissions::from_mode(0o755))
+}
+
+#[cfg(not(unix))]
+fn make_executable(_file: &File) -> io::Result<()> {
+    Ok(())
+}
+
+/// Reads the entire source file, exiting if it cannot be read
+fn read_source(source_path: &Path) -> Vec<u8> {
+    if !source_path.exists() || !source_path.is_file() {
+        exit_with_error!("Not a valid file: '{}'", source_path.display());
+    }
+
+    let mut f = File::open(source_path).unwrap_or_else(|e| {
+        exit_with_error!("Could not open source file: {}", e);
+    });
+
+    let mut bytes = Vec::new();
+    f.read_to_end(&mut bytes).expect("Fatal: Could not read source file");
+    bytes
+}
+
+/// Loads the program from bytecode or precompiles it from source, exiting with an error if
+/// neither is possible
+///
+/// Bytecode is recognized by its contents rather than its file extension. The optimization
+/// options in the arguments are ignored for bytecode since it is already precompiled.
+fn load_program(args: &ArgMatches, bytes: &[u8], source_path: &Path) -> Bytecode {
+    if Bytecode::is_bytecode(bytes) {
+        return Bytecode::from_bytes(bytes).unwrap_or_else(|err| {
+            exit_with_error!("Could not load '{}': {}", source_path.display(), err);
+        });
+    }
+
+    let (program, source_map) = precompile_source(args, bytes, source_path);
+    let opt = match args.value_of("passes") {
+        // Running no passes is exactly what -O0 does
+        Some(names) if names.split(',').all(|name| name.trim().is_empty()) => Some(OptimizationLevel::Off),
+        Some(_) => None,
+        // We can call unwrap() because the validation is already done by clap
+        None => Some(args.value_of("optimize").unwrap().parse().unwrap()),
+    };
+    Bytecode {program, opt, source_map: Some(source_map)}
+}
+
+/// Precompiles the source with the optimization level or passes given in the arguments,
+/// exiting with an error if the source cannot be precompiled
+fn precompile_source(args: &ArgMatches, bytes: &[u8], source_path: &Path) -> (Vec<Instruction>, SourceMap) {
+    let result = if let Some(names) = args.value_of("passes") {
+        let mut passes = PassManager::new();
+        for name in names.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            passes.add_boxed(builtin_pass(name).unwrap_or_else(|| {
+                exit_with_error!("Unknown optimization pass: '{}'", name);
+            }));
+        }
+        precompile_with_passes(bytes.iter(), &passes)
+    } else {
+        // We can call unwrap() because the validation is already done by clap
+        let opt = args.value_of("optimize").unwrap().parse().unwrap();
+        precompile_with_source_map(bytes.iter(), opt)
+    };
+    result.unwrap_or_else(|err| {
+        print_parse_error(bytes, source_path, &err);
+        process::exit(1);
+    })
+}
+
+/// Prints every unmatched jump in the error along with an excerpt of the source that
+/// points to where it was found
+fn print_parse_error(source: &[u8], source_path: &Path, err: &ParseError) {
+    for jump in &err.unmatched {
+        let pos = jump.position;
+        let line = source.split(|&c| c == b'\n').nth(pos.line - 1).unwrap_or(&[]);
+        let line = String::from_utf8_lossy(line);
+        let line = line.trim_end_matches('\r');
+        // Columns are in bytes, but the caret needs to be placed under the right character
+        let caret_offset = String::from_utf8_lossy(&source[pos.offset + 1 - pos.column..pos.offset])
+            .chars().count();
+        let gutter = " ".repeat(pos.line.to_string().len());
+
+        eprintln!("{}: {}", "error".red().bold(), jump.to_string().bold());
+        eprintln!("{}{} {}:{}:{}", gutter, "-->".blue().bold(), source_path.display(), pos.line, pos.column);
+        eprintln!("{} {}", gutter, "|".blue().bold());
+        eprintln!("{} {} {}", pos.line.to_string().blue().bold(), "|".blue().bold(), line);
+        eprintln!("{} {} {}{}", gutter, "|".blue().bold(), " ".repeat(caret_offset), "^".red().bold());
     }
 }
 
 #[inline]
-fn format_human_readable(state: InterpreterState, delay: u64, instruction_width: usize) {
+fn format_human_readable<C: Cell>(state: InterpreterState<C>, source_map: &SourceMap, delay: u64, instruction_width: usize) -> Control {
     use Instruction::*;
 
     let pointer = state.current_pointer;
 
     let current_instruction = format!("#{:<3}", state.current_instruction);
+    let span = source_map.get(state.current_instruction)
+        .expect("bug: instruction missing from the source map");
+    let source_position = format!("{:>4}:{:<3}", span.start.line, span.start.column);
 
     let instr = state.instruction;
     let instruction = match instr {
-        Right(..) | Left(..) => instr.to_string().on_cyan(),
-        Increment(..) | Decrement(..) => instr.to_string().on_green(),
-        Write => instr.to_string().on_purple(),
-        Read => instr.to_string().on_yellow(),
+        Right(..) | Left(..) | ScanRight(..) | ScanLeft(..) => instr.to_string().on_cyan(),
+        Increment(..) | Decrement(..) | Set(..) | MulAdd { .. } | AddAt { .. } => instr.to_string().on_green(),
+        Write | WriteAt { .. } => instr.to_string().on_purple(),
+        Read | ReadAt { .. } => instr.to_string().on_yellow(),
         JumpForwardIfZero { .. } | JumpBackwardUnlessZero { .. } => {
             instr.to_string().on_blue()
         },
@@ -147,8 +670,9 @@ fn format_human_readable(state: InterpreterState, delay: u64, instruction_width:
     });
 
     eprintln!(
-        "{} {:instruction_width$} {}",
+        "{} {} {:instruction_width$} {}",
         current_instruction.normal(),
+        source_position.dimmed(),
         instruction,
         memory,
 
@@ -156,17 +680,25 @@ fn format_human_readable(state: InterpreterState, delay: u64, instruction_width:
     );
 
     thread::sleep(Duration::from_millis(delay));
+    Control::Continue
 }
 
 #[inline]
-fn format_json(state: InterpreterState, delay: u64) {
+fn format_json<C: Cell>(state: InterpreterState<C>, source_map: &SourceMap, delay: u64) -> Control {
+    let span = source_map.get(state.current_instruction)
+        .expect("bug: instruction missing from the source map");
     eprintln!(
-        "{{\"currentInstructionIndex\": {}, \"instruction\": \"{}\", \"currentPointer\": {}, \"memory\": \"{}\"}}",
+        "{{\"currentInstructionIndex\": {}, \"sourceOffset\": {}, \"sourceLength\": {}, \"sourceLine\": {}, \"sourceColumn\": {}, \"instruction\": \"{}\", \"currentPointer\": {}, \"memory\": \"{}\"}}",
         state.current_instruction,
+        span.start.offset,
+        span.len,
+        span.start.line,
+        span.start.column,
         state.instruction,
         state.current_pointer,
         state.memory.iter().fold(String::new(), |acc, v| format!("{} {}", acc, v))
     );
 
     thread::sleep(Duration::from_millis(delay));
+    Control::Continue
 }
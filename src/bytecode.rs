@@ -0,0 +1,450 @@
+use super::{Instruction, OptimizationLevel, SourceMap, SourcePosition, Span, BytecodeError};
+
+/// The first bytes of every bytecode file
+pub const BYTECODE_MAGIC: &[u8; 4] = b"\x89BFC";
+/// The version of the bytecode format written by `Bytecode::to_bytes`
+///
+/// Bytecode written in any other version of the format is rejected when it is loaded.
+pub const BYTECODE_VERSION: u16 = 1;
+
+// Opcodes of each kind of instruction
+const RIGHT: u8 = 0;
+const LEFT: u8 = 1;
+const INCREMENT: u8 = 2;
+const DECREMENT: u8 = 3;
+const WRITE: u8 = 4;
+const READ: u8 = 5;
+const JUMP_FORWARD_IF_ZERO: u8 = 6;
+const JUMP_BACKWARD_UNLESS_ZERO: u8 = 7;
+const SET: u8 = 8;
+const MUL_ADD: u8 = 9;
+const SCAN_RIGHT: u8 = 10;
+const SCAN_LEFT: u8 = 11;
+const ADD_AT: u8 = 12;
+const WRITE_AT: u8 = 13;
+const READ_AT: u8 = 14;
+
+// Stored in place of the optimization level when the program was optimized with a custom set
+// of passes
+const CUSTOM_PASSES: u8 = 0xFF;
+// Set in the flags when the bytecode contains a source map
+const HAS_SOURCE_MAP: u8 = 1;
+
+/// A precompiled program that can be saved as bytecode so that it can be run later without
+/// precompiling it again
+///
+/// The format starts with `BYTECODE_MAGIC` and a little endian `BYTECODE_VERSION`, followed
+/// by the optimization level, the instructions with every jump target resolved and the
+/// optional source map. It ends with a CRC-32 checksum of everything before it.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Bytecode {
+    pub program: Vec<Instruction>,
+    /// The optimization level the program was precompiled at or None if it was optimized with
+    /// a custom set of passes
+    pub opt: Option<OptimizationLevel>,
+    /// Only needed to debug the program
+    pub source_map: Option<SourceMap>,
+}
+
+impl Bytecode {
+    /// Returns true if the data starts with the bytecode magic number
+    pub fn is_bytecode(bytes: &[u8]) -> bool {
+        bytes.starts_with(BYTECODE_MAGIC)
+    }
+
+    /// Serializes the program
+    ///
+    /// # Panics
+    /// Panics if the jumps in the program are not balanced. This cannot happen for a program
+    /// returned by `precompile`.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(BYTECODE_MAGIC);
+        bytes.extend_from_slice(&BYTECODE_VERSION.to_le_bytes());
+        bytes.push(match self.opt {
+            Some(opt) => opt_to_byte(opt),
+            None => CUSTOM_PASSES,
+        });
+        bytes.push(if self.source_map.is_some() { HAS_SOURCE_MAP } else { 0 });
+
+        let targets = resolve_jumps(&self.program).expect("Mismatched jump instruction");
+        write_varint(&mut bytes, self.program.len() as u64);
+        for (index, &instr) in self.program.iter().enumerate() {
+            match instr {
+                Instruction::Right(amount) => write_unsigned(&mut bytes, RIGHT, &[amount]),
+                Instruction::Left(amount) => write_unsigned(&mut bytes, LEFT, &[amount]),
+                Instruction::Increment(amount) => write_unsigned(&mut bytes, INCREMENT, &[amount]),
+                Instruction::Decrement(amount) => write_unsigned(&mut bytes, DECREMENT, &[amount]),
+                Instruction::Write => bytes.push(WRITE),
+                Instruction::Read => bytes.push(READ),
+                Instruction::JumpForwardIfZero { .. } => {
+                    write_unsigned(&mut bytes, JUMP_FORWARD_IF_ZERO, &[targets[index]]);
+                },
+                Instruction::JumpBackwardUnlessZero { .. } => {
+                    write_unsigned(&mut bytes, JUMP_BACKWARD_UNLESS_ZERO, &[targets[index]]);
+                },
+                Instruction::Set(value) => write_signed(&mut bytes, SET, &[value]),
+                Instruction::MulAdd {offset, factor} => write_signed(&mut bytes, MUL_ADD, &[offset, factor]),
+                Instruction::ScanRight(stride) => write_unsigned(&mut bytes, SCAN_RIGHT, &[stride]),
+                Instruction::ScanLeft(stride) => write_unsigned(&mut bytes, SCAN_LEFT, &[stride]),
+                Instruction::AddAt {offset, delta} => write_signed(&mut bytes, ADD_AT, &[offset, delta]),
+                Instruction::WriteAt {offset} => write_signed(&mut bytes, WRITE_AT, &[offset]),
+                Instruction::ReadAt {offset} => write_signed(&mut bytes, READ_AT, &[offset]),
+            }
+        }
+
+        if let Some(ref source_map) = self.source_map {
+            assert_eq!(source_map.len(), self.program.len(),
+                "The source map must have a span for every instruction");
+            for span in source_map.iter() {
+                write_varint(&mut bytes, span.start.offset as u64);
+                write_varint(&mut bytes, span.start.line as u64);
+                write_varint(&mut bytes, span.start.column as u64);
+                write_varint(&mut bytes, span.len as u64);
+            }
+        }
+
+        let checksum = crc32(&bytes);
+        bytes.extend_from_slice(&checksum.to_le_bytes());
+        bytes
+    }
+
+    /// Loads a program that was serialized with `to_bytes`
+    ///
+    /// The matching instruction of every jump is filled in, so the program can be passed
+    /// straight to the interpreter.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Bytecode, BytecodeError> {
+        if !Bytecode::is_bytecode(bytes) {
+            return Err(BytecodeError::NotBytecode);
+        }
+        let mut reader = Reader {bytes, position: BYTECODE_MAGIC.len()};
+        let version = reader.bytes(2)?;
+        let version = u16::from_le_bytes([version[0], version[1]]);
+        if version != BYTECODE_VERSION {
+            return Err(BytecodeError::UnsupportedVersion {version});
+        }
+
+        let checksum_start = bytes.len().checked_sub(4)
+            .filter(|&start| start >= reader.position)
+            .ok_or(BytecodeError::Malformed {reason: "missing checksum"})?;
+        let (contents, checksum) = bytes.split_at(checksum_start);
+        if crc32(contents) != u32::from_le_bytes([checksum[0], checksum[1], checksum[2], checksum[3]]) {
+            return Err(BytecodeError::ChecksumMismatch);
+        }
+        reader.bytes = contents;
+
+        let opt = match reader.byte()? {
+            CUSTOM_PASSES => None,
+            byte => Some(opt_from_byte(byte).ok_or(BytecodeError::Malformed {reason: "unknown optimization level"})?),
+        };
+        let flags = reader.byte()?;
+        if flags & !HAS_SOURCE_MAP != 0 {
+            return Err(BytecodeError::Malformed {reason: "unknown flags"});
+        }
+
+        let len = reader.usize()?;
+        // Every instruction takes at least one byte, so this avoids allocating a huge vector
+        // for a bogus length
+        let mut program = Vec::with_capacity(len.min(contents.len()));
+        let mut targets = Vec::with_capacity(len.min(contents.len()));
+        for _ in 0..len {
+            let mut target = None;
+            program.push(match reader.byte()? {
+                RIGHT => Instruction::Right(reader.usize()?),
+                LEFT => Instruction::Left(reader.usize()?),
+                INCREMENT => Instruction::Increment(reader.usize()?),
+                DECREMENT => Instruction::Decrement(reader.usize()?),
+                WRITE => Instruction::Write,
+                READ => Instruction::Read,
+                JUMP_FORWARD_IF_ZERO => {
+                    let matching = reader.usize()?;
+                    target = Some(matching);
+                    Instruction::JumpForwardIfZero {matching: Some(matching)}
+                },
+                JUMP_BACKWARD_UNLESS_ZERO => {
+                    let matching = reader.usize()?;
+                    target = Some(matching);
+                    Instruction::JumpBackwardUnlessZero {matching}
+                },
+                SET => Instruction::Set(reader.isize()?),
+                MUL_ADD => Instruction::MulAdd {offset: reader.isize()?, factor: reader.isize()?},
+                SCAN_RIGHT => Instruction::ScanRight(reader.usize()?),
+                SCAN_LEFT => Instruction::ScanLeft(reader.usize()?),
+                ADD_AT => Instruction::AddAt {offset: reader.isize()?, delta: reader.isize()?},
+                WRITE_AT => Instruction::WriteAt {offset: reader.isize()?},
+                READ_AT => Instruction::ReadAt {offset: reader.isize()?},
+                _ => return Err(BytecodeError::Malformed {reason: "unknown instruction"}),
+            });
+            targets.push(target);
+        }
+
+        // The stored targets are checked instead of trusted so that a program can never jump
+        // somewhere its jumps do not match up
+        let resolved = resolve_jumps(&program).ok_or(BytecodeError::Malformed {reason: "mismatched jump instruction"})?;
+        if targets.iter().zip(&resolved).any(|(target, &resolved)| target.is_some_and(|target| target != resolved)) {
+            return Err(BytecodeError::Malformed {reason: "incorrect jump target"});
+        }
+
+        let source_map = if flags & HAS_SOURCE_MAP != 0 {
+            let mut spans = Vec::with_capacity(len);
+            for _ in 0..len {
+                let offset = reader.usize()?;
+                let line = reader.usize()?;
+                let column = reader.usize()?;
+                let len = reader.usize()?;
+                spans.push(Span {start: SourcePosition {offset, line, column}, len});
+            }
+            Some(SourceMap::new(spans))
+        }
+        else {
+            None
+        };
+
+        if reader.position != contents.len() {
+            return Err(BytecodeError::Malformed {reason: "unexpected data after the program"});
+        }
+
+        Ok(Bytecode {program, opt, source_map})
+    }
+}
+
+/// Returns the index that each jump instruction jumps to (the instruction after its match)
+/// or None if the jumps are not balanced
+///
+/// Instructions that are not jumps are given a target of zero.
+fn resolve_jumps(program: &[Instruction]) -> Option<Vec<usize>> {
+    let mut targets = vec![0; program.len()];
+    let mut jump_stack = Vec::new();
+    for (index, &instr) in program.iter().enumerate() {
+        match instr {
+            Instruction::JumpForwardIfZero { .. } => jump_stack.push(index),
+            Instruction::JumpBackwardUnlessZero { .. } => {
+                let forward = jump_stack.pop()?;
+                targets[forward] = index + 1;
+                targets[index] = forward + 1;
+            },
+            _ => {},
+        }
+    }
+    if jump_stack.is_empty() { Some(targets) } else { None }
+}
+
+fn opt_to_byte(opt: OptimizationLevel) -> u8 {
+    match opt {
+        OptimizationLevel::Off => 0,
+        OptimizationLevel::Speed => 1,
+        OptimizationLevel::Aggressive => 2,
+        OptimizationLevel::Maximum => 3,
+        OptimizationLevel::Size => 4,
+    }
+}
+
+fn opt_from_byte(byte: u8) -> Option<OptimizationLevel> {
+    match byte {
+        0 => Some(OptimizationLevel::Off),
+        1 => Some(OptimizationLevel::Speed),
+        2 => Some(OptimizationLevel::Aggressive),
+        3 => Some(OptimizationLevel::Maximum),
+        4 => Some(OptimizationLevel::Size),
+        _ => None,
+    }
+}
+
+fn write_unsigned(bytes: &mut Vec<u8>, opcode: u8, operands: &[usize]) {
+    bytes.push(opcode);
+    for &operand in operands {
+        write_varint(bytes, operand as u64);
+    }
+}
+
+fn write_signed(bytes: &mut Vec<u8>, opcode: u8, operands: &[isize]) {
+    bytes.push(opcode);
+    for &operand in operands {
+        // Zigzag encoding keeps small negative numbers small
+        let operand = operand as i64;
+        write_varint(bytes, ((operand << 1) ^ (operand >> 63)) as u64);
+    }
+}
+
+/// Writes the value in the LEB128 format: seven bits at a time with the high bit set on every
+/// byte except the last one
+fn write_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+/// Reads values from the contents of bytecode
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], BytecodeError> {
+        let bytes = self.bytes.get(self.position..self.position + len)
+            .ok_or(BytecodeError::Malformed {reason: "unexpected end of data"})?;
+        self.position += len;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, BytecodeError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn varint(&mut self) -> Result<u64, BytecodeError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= ((byte & 0x7F) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(BytecodeError::Malformed {reason: "number is too large"})
+    }
+
+    fn usize(&mut self) -> Result<usize, BytecodeError> {
+        let value = self.varint()?;
+        if value > usize::MAX as u64 {
+            return Err(BytecodeError::Malformed {reason: "number is too large"});
+        }
+        Ok(value as usize)
+    }
+
+    fn isize(&mut self) -> Result<isize, BytecodeError> {
+        let value = self.varint()?;
+        let value = ((value >> 1) as i64) ^ -((value & 1) as i64);
+        if value < isize::MIN as i64 || value > isize::MAX as i64 {
+            return Err(BytecodeError::Malformed {reason: "number is too large"});
+        }
+        Ok(value as isize)
+    }
+}
+
+/// The CRC-32 checksum (as used by zlib and PNG) of the data
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{precompile_with_source_map, interpret, Control};
+    use super::super::Instruction::*;
+
+    /// Replaces the checksum at the end of the bytecode so that it matches the contents again
+    fn fix_checksum(bytes: &mut [u8]) {
+        let len = bytes.len() - 4;
+        let checksum = crc32(&bytes[..len]);
+        bytes[len..].copy_from_slice(&checksum.to_le_bytes());
+    }
+
+    #[test]
+    fn checksum() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn round_trip() {
+        let source = include_bytes!("../examples/qsort.bf");
+        for &opt in &[OptimizationLevel::Off, OptimizationLevel::Maximum] {
+            let (program, source_map) = precompile_with_source_map(source.iter(), opt).unwrap();
+            let bytecode = Bytecode {program: program.clone(), opt: Some(opt), source_map: Some(source_map)};
+            let bytes = bytecode.to_bytes();
+            assert!(Bytecode::is_bytecode(&bytes));
+            let loaded = Bytecode::from_bytes(&bytes).unwrap();
+            assert_eq!(loaded.opt, Some(opt));
+            assert_eq!(loaded.source_map, bytecode.source_map);
+
+            // The only difference should be the filled in jump targets
+            assert_eq!(loaded.program.len(), program.len());
+            for (&loaded, &original) in loaded.program.iter().zip(&program) {
+                match (loaded, original) {
+                    (JumpForwardIfZero {matching: Some(_)}, JumpForwardIfZero {matching: None}) => {},
+                    _ => assert_eq!(loaded, original),
+                }
+            }
+
+            let mut expected = Vec::new();
+            interpret(&b"bytecode\n"[..], &mut expected, program, |_| Control::Continue).unwrap();
+            let mut output = Vec::new();
+            interpret(&b"bytecode\n"[..], &mut output, loaded.program, |_| Control::Continue).unwrap();
+            assert_eq!(output, expected);
+        }
+    }
+
+    #[test]
+    fn operands() {
+        let bytecode = Bytecode {
+            program: vec![
+                Right(usize::MAX),
+                JumpForwardIfZero {matching: Some(4)},
+                MulAdd {offset: isize::MIN, factor: -1},
+                JumpBackwardUnlessZero {matching: 2},
+                AddAt {offset: isize::MAX, delta: 0},
+                ReadAt {offset: -64},
+                WriteAt {offset: 64},
+                Set(-300),
+            ],
+            opt: None,
+            source_map: None,
+        };
+        assert_eq!(Bytecode::from_bytes(&bytecode.to_bytes()), Ok(bytecode));
+    }
+
+    #[test]
+    fn invalid() {
+        let bytecode = Bytecode {
+            program: vec![JumpForwardIfZero {matching: None}, Write, JumpBackwardUnlessZero {matching: 1}],
+            opt: Some(OptimizationLevel::Speed),
+            source_map: None,
+        };
+        let bytes = bytecode.to_bytes();
+        // magic, version, level, flags, length, then the instructions
+        assert_eq!(&bytes[..13], &[0x89, b'B', b'F', b'C', 1, 0, 1, 0, 3, 6, 3, 4, 7]);
+
+        assert_eq!(Bytecode::from_bytes(b"+[.]"), Err(BytecodeError::NotBytecode));
+        assert_eq!(Bytecode::from_bytes(&bytes[..5]), Err(BytecodeError::Malformed {reason: "unexpected end of data"}));
+
+        let mut newer = bytes.clone();
+        newer[4] = 2;
+        assert_eq!(Bytecode::from_bytes(&newer), Err(BytecodeError::UnsupportedVersion {version: 2}));
+
+        let mut corrupt = bytes.clone();
+        corrupt[11] = READ;
+        assert_eq!(Bytecode::from_bytes(&corrupt), Err(BytecodeError::ChecksumMismatch));
+
+        let mut unknown = bytes.clone();
+        unknown[11] = 200;
+        fix_checksum(&mut unknown);
+        assert_eq!(Bytecode::from_bytes(&unknown), Err(BytecodeError::Malformed {reason: "unknown instruction"}));
+
+        let mut wrong_target = bytes.clone();
+        wrong_target[10] = 2;
+        fix_checksum(&mut wrong_target);
+        assert_eq!(Bytecode::from_bytes(&wrong_target), Err(BytecodeError::Malformed {reason: "incorrect jump target"}));
+
+        let mut unbalanced = bytes.clone();
+        // Replaces the "]" and its target with "." instructions
+        unbalanced[12] = WRITE;
+        unbalanced[13] = WRITE;
+        unbalanced[8] = 4;
+        fix_checksum(&mut unbalanced);
+        assert_eq!(Bytecode::from_bytes(&unbalanced), Err(BytecodeError::Malformed {reason: "mismatched jump instruction"}));
+
+        let mut trailing = bytes[..bytes.len() - 4].to_vec();
+        trailing.extend_from_slice(&[WRITE, 0, 0, 0, 0]);
+        fix_checksum(&mut trailing);
+        assert_eq!(Bytecode::from_bytes(&trailing), Err(BytecodeError::Malformed {reason: "unexpected data after the program"}));
+    }
+}
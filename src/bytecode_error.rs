@@ -0,0 +1,49 @@
+use std::fmt;
+use std::error::Error;
+
+/// Returned when bytecode could not be loaded
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum BytecodeError {
+    /// The data does not start with the bytecode magic number, so it is not bytecode at all
+    NotBytecode,
+    /// The bytecode was written in a different version of the format than the one this version
+    /// of the crate reads
+    UnsupportedVersion {
+        version: u16,
+    },
+    /// The checksum stored in the bytecode does not match its contents
+    ChecksumMismatch,
+    /// The bytecode passed its checksum but does not describe a valid program
+    Malformed {
+        reason: &'static str,
+    },
+}
+
+impl fmt::Display for BytecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BytecodeError::NotBytecode => write!(f, "Not a bytecode file"),
+            BytecodeError::UnsupportedVersion {version} => {
+                write!(f, "Unsupported bytecode version {} (expected version {}), rebuild the program",
+                    version, super::BYTECODE_VERSION)
+            },
+            BytecodeError::ChecksumMismatch => write!(f, "Bytecode checksum does not match, the file is corrupt"),
+            BytecodeError::Malformed {reason} => write!(f, "Malformed bytecode: {}", reason),
+        }
+    }
+}
+
+impl Error for BytecodeError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display() {
+        assert_eq!(BytecodeError::UnsupportedVersion {version: 7}.to_string(),
+            "Unsupported bytecode version 7 (expected version 1), rebuild the program");
+        assert_eq!(BytecodeError::Malformed {reason: "unknown instruction"}.to_string(),
+            "Malformed bytecode: unknown instruction");
+    }
+}
@@ -0,0 +1,74 @@
+use std::fmt;
+
+/// A cell of the tape that `interpret_with_config` and `Interpreter` run a program with
+///
+/// The specification uses 8-bit cells, but many programs assume wider cells. Arithmetic on
+/// every cell type wraps around. Input and output are still a byte at a time: reading sets the
+/// cell to the byte that was read and writing outputs only the lowest byte of the cell.
+pub trait Cell: Copy + Eq + Default + fmt::Debug + fmt::Display {
+    /// Converts a number to a cell, keeping only the lowest bits that fit in the cell
+    fn wrap(value: isize) -> Self;
+    /// Converts a byte of input to a cell
+    fn from_byte(byte: u8) -> Self;
+    /// The lowest byte of the cell, which is what gets written as output
+    fn low_byte(self) -> u8;
+    fn wrapping_add(self, other: Self) -> Self;
+    fn wrapping_sub(self, other: Self) -> Self;
+    fn wrapping_mul(self, other: Self) -> Self;
+}
+
+macro_rules! impl_cell {
+    ($($ty:ty),*) => {
+        $(
+            impl Cell for $ty {
+                #[inline]
+                fn wrap(value: isize) -> Self {
+                    value as $ty
+                }
+
+                #[inline]
+                fn from_byte(byte: u8) -> Self {
+                    byte as $ty
+                }
+
+                #[inline]
+                fn low_byte(self) -> u8 {
+                    self as u8
+                }
+
+                #[inline]
+                fn wrapping_add(self, other: Self) -> Self {
+                    <$ty>::wrapping_add(self, other)
+                }
+
+                #[inline]
+                fn wrapping_sub(self, other: Self) -> Self {
+                    <$ty>::wrapping_sub(self, other)
+                }
+
+                #[inline]
+                fn wrapping_mul(self, other: Self) -> Self {
+                    <$ty>::wrapping_mul(self, other)
+                }
+            }
+        )*
+    };
+}
+
+impl_cell!(u8, u16, u32);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wrapping() {
+        assert_eq!(u8::wrap(-1), 255);
+        assert_eq!(u16::wrap(-1), 65535);
+        assert_eq!(u32::wrap(256 + 4), 260);
+        assert_eq!(u8::wrap(256 + 4), 4);
+        assert_eq!(0u16.wrapping_sub(1).low_byte(), 255);
+        assert_eq!(300u32.low_byte(), 44);
+        assert_eq!(u16::from_byte(200).wrapping_mul(2), 400);
+    }
+}
@@ -0,0 +1,266 @@
+//! Emits x86-64 assembly for Linux in the GNU assembler (AT&T) syntax
+
+use super::{Options, Bounds, CodegenError, Code, check_jumps, check_max_tape_size};
+use super::{EXIT_INPUT_ERROR, EXIT_OUTPUT_ERROR, EXIT_OUT_OF_BOUNDS};
+use super::super::Instruction;
+
+/// Translates a precompiled program into x86-64 assembly for a static Linux executable that
+/// reads from stdin and writes to stdout using system calls
+///
+/// The result can be assembled and linked without any libraries:
+///
+/// ```text
+/// as -o program.o program.s && ld -o program program.o
+/// ```
+///
+/// # Errors
+/// Returns an error if the jumps in the program are not balanced, if the tape is too small for
+/// the offsets used by the program or if the tape has more than 2^31 - 1 cells
+pub fn emit(program: &[Instruction], options: &Options) -> Result<String, CodegenError> {
+    check_jumps(program)?;
+    let bounds = Bounds::new(program, options)?;
+    check_max_tape_size(options, i32::MAX as u64)?;
+
+    let mut code = Code::new();
+    code.line("# %rbx: address of the current cell");
+    code.line("# %r13: lowest address the pointer may be at");
+    code.line("# %r14: highest address the pointer may be at");
+    code.line("    .text");
+    code.line("    .globl _start");
+    code.line("_start:");
+    code.line(format!("    leaq tape+{}(%rip), %rbx", bounds.low));
+    code.line(format!("    leaq tape+{}(%rip), %r13", bounds.low));
+    code.line(format!("    leaq tape+{}(%rip), %r14", bounds.high - 1));
+
+    let mut labels = 0;
+    let mut loops = Vec::new();
+    for &instr in program {
+        match instr {
+            Instruction::Right(amount) => right(&mut code, amount, options),
+            Instruction::Left(amount) => left(&mut code, amount, options),
+            Instruction::ScanRight(stride) | Instruction::ScanLeft(stride) => {
+                labels += 1;
+                code.line(format!(".Lscan{}:", labels));
+                code.line("    cmpb $0, (%rbx)");
+                code.line(format!("    je .Lscan{}_done", labels));
+                match instr {
+                    Instruction::ScanRight(_) => right(&mut code, stride, options),
+                    _ => left(&mut code, stride, options),
+                }
+                code.line(format!("    jmp .Lscan{}", labels));
+                code.line(format!(".Lscan{}_done:", labels));
+            },
+            Instruction::Increment(amount) => code.line(format!("    addb ${}, (%rbx)", amount as u8)),
+            Instruction::Decrement(amount) => code.line(format!("    subb ${}, (%rbx)", amount as u8)),
+            Instruction::Set(value) => code.line(format!("    movb ${}, (%rbx)", value as u8)),
+            Instruction::MulAdd {offset, factor} => {
+                code.line("    movzbl (%rbx), %eax");
+                match factor as u8 {
+                    1 => code.line(format!("    addb %al, {}", cell(offset))),
+                    255 => code.line(format!("    subb %al, {}", cell(offset))),
+                    factor => {
+                        code.line(format!("    imull ${}, %eax, %eax", factor));
+                        code.line(format!("    addb %al, {}", cell(offset)));
+                    },
+                }
+            },
+            Instruction::AddAt {offset, delta} => code.line(format!("    addb ${}, {}", delta as u8, cell(offset))),
+            Instruction::Write | Instruction::WriteAt { .. } | Instruction::Read | Instruction::ReadAt { .. } => {
+                let (offset, routine) = match instr {
+                    Instruction::WriteAt {offset} => (offset, "output"),
+                    Instruction::ReadAt {offset} => (offset, "input"),
+                    Instruction::Write => (0, "output"),
+                    _ => (0, "input"),
+                };
+                code.line(format!("    leaq {}, %rsi", cell(offset)));
+                code.line(format!("    call {}", routine));
+            },
+            Instruction::JumpForwardIfZero { .. } => {
+                labels += 1;
+                loops.push(labels);
+                code.line("    cmpb $0, (%rbx)");
+                code.line(format!("    je .Lend{}", labels));
+                code.line(format!(".Lloop{}:", labels));
+            },
+            Instruction::JumpBackwardUnlessZero { .. } => {
+                let label = loops.pop().expect("bug: jumps were checked to be balanced");
+                code.line("    cmpb $0, (%rbx)");
+                code.line(format!("    jne .Lloop{}", label));
+                code.line(format!(".Lend{}:", label));
+            },
+        }
+    }
+
+    code.line("    movl $60, %eax");
+    code.line("    xorl %edi, %edi");
+    code.line("    syscall");
+    code.line("");
+    code.line("# Writes the byte at (%rsi) to stdout");
+    code.line("output:");
+    code.line("    movl $1, %eax");
+    code.line("    movl $1, %edi");
+    code.line("    movl $1, %edx");
+    code.line("    syscall");
+    code.line("    cmpq $1, %rax");
+    code.line("    jne output_error");
+    code.line("    ret");
+    code.line("");
+    code.line("# Reads a byte from stdin into (%rsi)");
+    code.line("input:");
+    code.line("    xorl %eax, %eax");
+    code.line("    xorl %edi, %edi");
+    code.line("    movl $1, %edx");
+    code.line("    syscall");
+    code.line("    testq %rax, %rax");
+    code.line("    js input_error");
+    code.line("    jnz 1f");
+    code.line("    # Running out of input is not an error, it just results in zero");
+    code.line("    movb $0, (%rsi)");
+    code.line("1:  ret");
+    code.line("");
+    error(&mut code, "past_end", "Moved past the end of the tape", EXIT_OUT_OF_BOUNDS);
+    error(&mut code, "past_start", "Moved past the start of the tape", EXIT_OUT_OF_BOUNDS);
+    error(&mut code, "output_error", "Could not write output", EXIT_OUTPUT_ERROR);
+    error(&mut code, "input_error", "Could not read input", EXIT_INPUT_ERROR);
+    code.line("    .bss");
+    code.line(format!("    .lcomm tape, {}", options.tape_size));
+    Ok(code.finish())
+}
+
+/// The operand for the cell at the given offset from the pointer
+fn cell(offset: isize) -> String {
+    if offset == 0 {
+        "(%rbx)".to_string()
+    }
+    else {
+        format!("{}(%rbx)", offset)
+    }
+}
+
+fn right(code: &mut Code, amount: usize, options: &Options) {
+    if amount >= options.tape_size {
+        code.line("    jmp past_end");
+        return;
+    }
+    code.line(format!("    addq ${}, %rbx", amount));
+    code.line("    cmpq %r14, %rbx");
+    code.line("    ja past_end");
+}
+
+fn left(code: &mut Code, amount: usize, options: &Options) {
+    if amount >= options.tape_size {
+        code.line("    jmp past_start");
+        return;
+    }
+    code.line(format!("    subq ${}, %rbx", amount));
+    code.line("    cmpq %r13, %rbx");
+    code.line("    jb past_start");
+}
+
+/// Emits a routine that prints the message to stderr and exits with the given code
+fn error(code: &mut Code, label: &str, message: &str, exit_code: i32) {
+    let message = format!("error: {}\n", message);
+    code.line(format!("{}:", label));
+    code.line("    movl $1, %eax");
+    code.line("    movl $2, %edi");
+    code.line(format!("    leaq {}_message(%rip), %rsi", label));
+    code.line(format!("    movl ${}, %edx", message.len()));
+    code.line("    syscall");
+    code.line("    movl $60, %eax");
+    code.line(format!("    movl ${}, %edi", exit_code));
+    code.line("    syscall");
+    code.line(format!("{}_message:", label));
+    code.line(format!("    .ascii \"{}\"", message.escape_default()));
+    code.line("");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::tests::{test_programs, expected_output, run, temp_path};
+    use super::super::super::precompile;
+    use super::super::super::Instruction::*;
+
+    use std::fs;
+    use std::process::Command;
+
+    #[test]
+    fn instructions() {
+        let code = emit(&[
+            Right(2),
+            JumpForwardIfZero {matching: None},
+            MulAdd {offset: -1, factor: 1},
+            MulAdd {offset: 2, factor: 3},
+            Set(0),
+            JumpBackwardUnlessZero {matching: 2},
+            ScanLeft(1),
+            WriteAt {offset: 3},
+        ], &Options {tape_size: 100}).unwrap();
+
+        assert!(code.contains("    leaq tape+1(%rip), %r13\n    leaq tape+96(%rip), %r14\n"));
+        assert!(code.contains("\
+    addq $2, %rbx
+    cmpq %r14, %rbx
+    ja past_end
+    cmpb $0, (%rbx)
+    je .Lend1
+.Lloop1:
+    movzbl (%rbx), %eax
+    addb %al, -1(%rbx)
+    movzbl (%rbx), %eax
+    imull $3, %eax, %eax
+    addb %al, 2(%rbx)
+    movb $0, (%rbx)
+    cmpb $0, (%rbx)
+    jne .Lloop1
+.Lend1:
+.Lscan2:
+    cmpb $0, (%rbx)
+    je .Lscan2_done
+    subq $1, %rbx
+    cmpq %r13, %rbx
+    jb past_start
+    jmp .Lscan2
+.Lscan2_done:
+    leaq 3(%rbx), %rsi
+    call output
+"));
+        assert!(code.ends_with("    .lcomm tape, 100\n"));
+    }
+
+    /// Assembles and links the emitted assembly with the GNU toolchain (if it is available)
+    /// and checks that it behaves exactly like the interpreter
+    #[test]
+    fn same_as_interpreter() {
+        if !cfg!(all(target_arch = "x86_64", target_os = "linux")) {
+            return;
+        }
+
+        let source_path = temp_path("asm-test.s");
+        let object_path = temp_path("asm-test.o");
+        let exe_path = temp_path("asm-test");
+        let build = |code: String| -> bool {
+            fs::write(&source_path, code).unwrap();
+            let assembled = Command::new("as").arg("-o").arg(&object_path).arg(&source_path).status();
+            match assembled {
+                Ok(status) => assert!(status.success(), "assembler failed"),
+                // No assembler available to test with
+                Err(_) => return false,
+            }
+            let linked = Command::new("ld").arg("-o").arg(&exe_path).arg(&object_path).status();
+            assert!(linked.unwrap().success(), "linker failed");
+            true
+        };
+
+        for (source, input, opt) in test_programs() {
+            let program = precompile(source.iter(), opt).unwrap();
+            if !build(emit(&program, &Options::default()).unwrap()) {
+                return;
+            }
+            assert_eq!(run(&mut Command::new(&exe_path), input), (0, expected_output(source, input)));
+        }
+
+        assert!(build(emit(&[Left(1)], &Options::default()).unwrap()));
+        assert_eq!(run(&mut Command::new(&exe_path), b"").0, EXIT_OUT_OF_BOUNDS);
+    }
+}
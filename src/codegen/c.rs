@@ -0,0 +1,178 @@
+//! Emits a standalone C99 program
+
+use super::{Options, Bounds, CodegenError, Code, check_jumps, offset_term};
+use super::{EXIT_INPUT_ERROR, EXIT_OUTPUT_ERROR, EXIT_OUT_OF_BOUNDS};
+use super::super::Instruction;
+
+/// Translates a precompiled program into the source of a C99 program that reads from stdin
+/// and writes to stdout
+///
+/// # Errors
+/// Returns an error if the jumps in the program are not balanced or if the tape is too small
+/// for the offsets used by the program
+pub fn emit(program: &[Instruction], options: &Options) -> Result<String, CodegenError> {
+    check_jumps(program)?;
+    let bounds = Bounds::new(program, options)?;
+
+    let mut code = Code::new();
+    code.line("#include <stdio.h>");
+    code.line("#include <stdlib.h>");
+    code.line("");
+    code.line(format!("#define TAPE_SIZE {}", options.tape_size));
+    code.line("/* The pointer must stay in this range so that every cell it addresses is in the tape */");
+    code.line(format!("#define LOW {}", bounds.low));
+    code.line(format!("#define HIGH {}", bounds.high));
+    code.line("");
+    code.line("static unsigned char tape[TAPE_SIZE];");
+    code.line("static size_t p = LOW;");
+    code.line("");
+    code.open("static void fail(const char *message, int code) {");
+    code.line("fprintf(stderr, \"error: %s\\n\", message);");
+    code.line("exit(code);");
+    code.close("}");
+    code.line("");
+    code.open("static void right(size_t amount) {");
+    code.open("if (amount >= HIGH - p) {");
+    code.line(format!("fail(\"Moved past the end of the tape\", {});", EXIT_OUT_OF_BOUNDS));
+    code.close("}");
+    code.line("p += amount;");
+    code.close("}");
+    code.line("");
+    code.open("static void left(size_t amount) {");
+    code.open("if (amount > p - LOW) {");
+    code.line(format!("fail(\"Moved past the start of the tape\", {});", EXIT_OUT_OF_BOUNDS));
+    code.close("}");
+    code.line("p -= amount;");
+    code.close("}");
+    code.line("");
+    code.open("static void output(unsigned char value) {");
+    code.open("if (putchar(value) == EOF) {");
+    code.line(format!("fail(\"Could not write output\", {});", EXIT_OUTPUT_ERROR));
+    code.close("}");
+    code.close("}");
+    code.line("");
+    code.open("static unsigned char input(void) {");
+    code.line("int c;");
+    code.line("/* Make sure any prompt is visible before waiting for input */");
+    code.line("fflush(stdout);");
+    code.line("c = getchar();");
+    code.open("if (c == EOF) {");
+    code.open("if (ferror(stdin)) {");
+    code.line(format!("fail(\"Could not read input\", {});", EXIT_INPUT_ERROR));
+    code.close("}");
+    code.line("/* Running out of input is not an error, it just results in zero */");
+    code.line("return 0;");
+    code.close("}");
+    code.line("return (unsigned char) c;");
+    code.close("}");
+    code.line("");
+    code.open("int main(void) {");
+
+    for &instr in program {
+        match instr {
+            Instruction::Right(amount) => code.line(format!("right({});", amount)),
+            Instruction::Left(amount) => code.line(format!("left({});", amount)),
+            Instruction::ScanRight(stride) => code.line(format!("while (tape[p]) right({});", stride)),
+            Instruction::ScanLeft(stride) => code.line(format!("while (tape[p]) left({});", stride)),
+            Instruction::Increment(amount) => code.line(format!("tape[p] += {};", amount as u8)),
+            Instruction::Decrement(amount) => code.line(format!("tape[p] -= {};", amount as u8)),
+            Instruction::Set(value) => code.line(format!("tape[p] = {};", value as u8)),
+            Instruction::MulAdd {offset, factor} => match factor as u8 {
+                1 => code.line(format!("tape[p{}] += tape[p];", offset_term(offset))),
+                factor => code.line(format!("tape[p{}] += tape[p] * {};", offset_term(offset), factor)),
+            },
+            Instruction::AddAt {offset, delta} => {
+                code.line(format!("tape[p{}] += {};", offset_term(offset), delta as u8));
+            },
+            Instruction::Write => code.line("output(tape[p]);"),
+            Instruction::WriteAt {offset} => code.line(format!("output(tape[p{}]);", offset_term(offset))),
+            Instruction::Read => code.line("tape[p] = input();"),
+            Instruction::ReadAt {offset} => code.line(format!("tape[p{}] = input();", offset_term(offset))),
+            Instruction::JumpForwardIfZero { .. } => code.open("while (tape[p]) {"),
+            Instruction::JumpBackwardUnlessZero { .. } => code.close("}"),
+        }
+    }
+
+    code.open("if (fflush(stdout) != 0) {");
+    code.line(format!("fail(\"Could not write output\", {});", EXIT_OUTPUT_ERROR));
+    code.close("}");
+    code.line("return 0;");
+    code.close("}");
+    Ok(code.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::tests::{test_programs, expected_output, run, temp_path};
+    use super::super::super::Instruction::*;
+
+    use std::fs;
+    use std::process::Command;
+
+    #[test]
+    fn instructions() {
+        let code = emit(&[
+            Right(2),
+            JumpForwardIfZero {matching: None},
+            MulAdd {offset: -1, factor: 1},
+            MulAdd {offset: 2, factor: -3},
+            Set(0),
+            JumpBackwardUnlessZero {matching: 2},
+            ScanLeft(1),
+            AddAt {offset: 3, delta: -1},
+            WriteAt {offset: 3},
+            Read,
+            Decrement(2),
+        ], &Options {tape_size: 100}).unwrap();
+
+        assert!(code.contains("#define TAPE_SIZE 100\n"));
+        assert!(code.contains("#define LOW 1\n"));
+        assert!(code.contains("#define HIGH 97\n"));
+        assert!(code.ends_with("\
+int main(void) {
+    right(2);
+    while (tape[p]) {
+        tape[p - 1] += tape[p];
+        tape[p + 2] += tape[p] * 253;
+        tape[p] = 0;
+    }
+    while (tape[p]) left(1);
+    tape[p + 3] += 255;
+    output(tape[p + 3]);
+    tape[p] = input();
+    tape[p] -= 2;
+    if (fflush(stdout) != 0) {
+        fail(\"Could not write output\", 3);
+    }
+    return 0;
+}
+"));
+    }
+
+    /// Compiles the emitted C with the system C compiler (if there is one) and checks that it
+    /// behaves exactly like the interpreter
+    #[test]
+    fn same_as_interpreter() {
+        let source_path = temp_path("c-test.c");
+        let exe_path = temp_path("c-test");
+
+        for (source, input, opt) in test_programs() {
+            let program = super::super::super::precompile(source.iter(), opt).unwrap();
+            fs::write(&source_path, emit(&program, &Options::default()).unwrap()).unwrap();
+            let compiled = Command::new("cc").arg("-std=c99").arg("-O1").arg("-o").arg(&exe_path)
+                .arg(&source_path).status();
+            match compiled {
+                Ok(status) => assert!(status.success(), "C compiler failed"),
+                // No C compiler available to test with
+                Err(_) => return,
+            }
+
+            assert_eq!(run(&mut Command::new(&exe_path), input), (0, expected_output(source, input)));
+        }
+
+        fs::write(&source_path, emit(&[Right(10)], &Options {tape_size: 10}).unwrap()).unwrap();
+        assert!(Command::new("cc").arg("-o").arg(&exe_path).arg(&source_path).status().unwrap().success());
+        assert_eq!(run(&mut Command::new(&exe_path), b"").0, EXIT_OUT_OF_BOUNDS);
+    }
+}
@@ -0,0 +1,53 @@
+use std::fmt;
+use std::error::Error;
+
+/// Returned when a program could not be compiled with the given options
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum CodegenError {
+    /// The jump instructions in the program are not balanced. This cannot happen for a program
+    /// returned by `precompile`.
+    MismatchedJumps,
+    /// The tape has fewer cells than a single instruction of the program addresses at once
+    TapeTooSmall {
+        tape_size: usize,
+        /// The smallest tape that the program fits in
+        required: usize,
+    },
+    /// The tape has more cells than the target language or platform can address
+    TapeTooLarge {
+        tape_size: usize,
+        /// The largest tape the target supports
+        max: u64,
+    },
+}
+
+impl fmt::Display for CodegenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CodegenError::MismatchedJumps => write!(f, "Mismatched jump instruction"),
+            CodegenError::TapeTooSmall {tape_size, required} => {
+                write!(f, "A tape of {} cells is too small for this program (it needs at least {} cells)",
+                    tape_size, required)
+            },
+            CodegenError::TapeTooLarge {tape_size, max} => {
+                write!(f, "A tape of {} cells is too large for this target (it can have at most {} cells)",
+                    tape_size, max)
+            },
+        }
+    }
+}
+
+impl Error for CodegenError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display() {
+        assert_eq!(CodegenError::TapeTooSmall {tape_size: 2, required: 4}.to_string(),
+            "A tape of 2 cells is too small for this program (it needs at least 4 cells)");
+        assert_eq!(CodegenError::TapeTooLarge {tape_size: 1 << 31, max: (1 << 31) - 1}.to_string(),
+            "A tape of 2147483648 cells is too large for this target (it can have at most 2147483647 cells)");
+    }
+}
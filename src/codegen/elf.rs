@@ -0,0 +1,293 @@
+//! Writes a static x86-64 Linux ELF executable directly, without needing an assembler or linker
+
+use super::{Options, Bounds, CodegenError, check_jumps, check_max_tape_size};
+use super::{EXIT_INPUT_ERROR, EXIT_OUTPUT_ERROR, EXIT_OUT_OF_BOUNDS};
+use super::super::Instruction;
+use super::super::x86_64::*;
+
+// The address that the file (headers, messages and code) is loaded at
+const BASE_ADDRESS: u64 = 0x40_0000;
+// The address of the tape, far enough away that the code never reaches it
+const TAPE_ADDRESS: u64 = 0x1_0000_0000;
+const ELF_HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+const PAGE_SIZE: u64 = 0x1000;
+
+/// Compiles a precompiled program into a static x86-64 Linux executable that reads from stdin
+/// and writes to stdout using system calls
+///
+/// The executable behaves exactly like the assembly emitted by `codegen::asm::emit`.
+///
+/// # Errors
+/// Returns an error if the jumps in the program are not balanced, if the tape is too small for
+/// the offsets used by the program or if the tape has more than 2^31 - 1 cells
+pub fn emit(program: &[Instruction], options: &Options) -> Result<Vec<u8>, CodegenError> {
+    check_jumps(program)?;
+    let bounds = Bounds::new(program, options)?;
+    check_max_tape_size(options, i32::MAX as u64)?;
+
+    // The error messages are placed right after the headers so that their addresses are
+    // known before the code is generated
+    let headers_size = ELF_HEADER_SIZE + 2 * PROGRAM_HEADER_SIZE;
+    let errors = [
+        ("Moved past the end of the tape", EXIT_OUT_OF_BOUNDS),
+        ("Moved past the start of the tape", EXIT_OUT_OF_BOUNDS),
+        ("Could not write output", EXIT_OUTPUT_ERROR),
+        ("Could not read input", EXIT_INPUT_ERROR),
+    ];
+    let mut messages = Vec::new();
+    let mut message_addresses = Vec::new();
+    for &(message, _) in &errors {
+        let message = format!("error: {}\n", message);
+        message_addresses.push((BASE_ADDRESS + (headers_size + messages.len()) as u64, message.len()));
+        messages.extend_from_slice(message.as_bytes());
+    }
+    let entry = BASE_ADDRESS + (headers_size + messages.len()) as u64;
+
+    let code = compile(program, bounds, options, &errors, &message_addresses);
+
+    let file_size = (headers_size + messages.len() + code.len()) as u64;
+    let mut elf = Vec::with_capacity(file_size as usize);
+    // ELF header: 64-bit, little endian, version 1, System V ABI
+    elf.extend_from_slice(&[0x7F, b'E', b'L', b'F', 2, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    elf.extend_from_slice(&2u16.to_le_bytes()); // executable file
+    elf.extend_from_slice(&0x3Eu16.to_le_bytes()); // x86-64
+    elf.extend_from_slice(&1u32.to_le_bytes());
+    elf.extend_from_slice(&entry.to_le_bytes());
+    elf.extend_from_slice(&(ELF_HEADER_SIZE as u64).to_le_bytes()); // program headers
+    elf.extend_from_slice(&0u64.to_le_bytes()); // no section headers
+    elf.extend_from_slice(&0u32.to_le_bytes());
+    elf.extend_from_slice(&(ELF_HEADER_SIZE as u16).to_le_bytes());
+    elf.extend_from_slice(&(PROGRAM_HEADER_SIZE as u16).to_le_bytes());
+    elf.extend_from_slice(&2u16.to_le_bytes());
+    elf.extend_from_slice(&0u16.to_le_bytes());
+    elf.extend_from_slice(&0u16.to_le_bytes());
+    elf.extend_from_slice(&0u16.to_le_bytes());
+
+    // The file itself is loaded as readable and executable
+    program_header(&mut elf, 0b101, 0, BASE_ADDRESS, file_size, file_size);
+    // The tape is zeroed, readable and writable memory that takes no space in the file
+    program_header(&mut elf, 0b110, 0, TAPE_ADDRESS, 0, options.tape_size as u64);
+    debug_assert_eq!(elf.len(), headers_size);
+
+    elf.extend_from_slice(&messages);
+    elf.extend_from_slice(&code);
+    Ok(elf)
+}
+
+fn program_header(elf: &mut Vec<u8>, flags: u32, offset: u64, address: u64, file_size: u64, memory_size: u64) {
+    elf.extend_from_slice(&1u32.to_le_bytes()); // loadable segment
+    elf.extend_from_slice(&flags.to_le_bytes());
+    elf.extend_from_slice(&offset.to_le_bytes());
+    elf.extend_from_slice(&address.to_le_bytes());
+    elf.extend_from_slice(&address.to_le_bytes());
+    elf.extend_from_slice(&file_size.to_le_bytes());
+    elf.extend_from_slice(&memory_size.to_le_bytes());
+    elf.extend_from_slice(&PAGE_SIZE.to_le_bytes());
+}
+
+/// Generates the machine code of the program
+///
+/// RBX holds the address of the current cell, R13 the lowest address the pointer may be at
+/// and R14 the highest address the pointer may be at.
+fn compile(program: &[Instruction], bounds: Bounds, options: &Options, errors: &[(&str, i32)], messages: &[(u64, usize)]) -> Vec<u8> {
+    let mut asm = Assembler::new(0);
+    let past_end = asm.new_label();
+    let past_start = asm.new_label();
+    let output_error = asm.new_label();
+    let input_error = asm.new_label();
+    let output = asm.new_label();
+    let input = asm.new_label();
+
+    asm.mov_imm(RBX, TAPE_ADDRESS + bounds.low as u64);
+    asm.mov_reg(R13, RBX);
+    asm.mov_imm(R14, TAPE_ADDRESS + bounds.high as u64 - 1);
+
+    let mut loops = Vec::new();
+    for &instr in program {
+        match instr {
+            Instruction::Right(amount) => right(&mut asm, amount, options, past_end),
+            Instruction::Left(amount) => left(&mut asm, amount, options, past_start),
+            Instruction::ScanRight(stride) | Instruction::ScanLeft(stride) => {
+                let start = asm.new_label();
+                let done = asm.new_label();
+                asm.bind(start);
+                asm.cmp_cell_zero();
+                asm.jcc(EQUAL, done);
+                match instr {
+                    Instruction::ScanRight(_) => right(&mut asm, stride, options, past_end),
+                    _ => left(&mut asm, stride, options, past_start),
+                }
+                asm.jmp(start);
+                asm.bind(done);
+            },
+            Instruction::Increment(amount) => asm.add_cell(0, amount as u8),
+            Instruction::Decrement(amount) => asm.add_cell(0, (amount as u8).wrapping_neg()),
+            Instruction::Set(value) => asm.set_cell(0, value as u8),
+            Instruction::MulAdd {offset, factor} => {
+                asm.load_cell(RAX, 0);
+                asm.mul_add_cell(offset, factor as u8);
+            },
+            Instruction::AddAt {offset, delta} => asm.add_cell(offset, delta as u8),
+            Instruction::Write => {
+                asm.lea_cell(RSI, 0);
+                asm.call(output);
+            },
+            Instruction::WriteAt {offset} => {
+                asm.lea_cell(RSI, offset);
+                asm.call(output);
+            },
+            Instruction::Read => {
+                asm.lea_cell(RSI, 0);
+                asm.call(input);
+            },
+            Instruction::ReadAt {offset} => {
+                asm.lea_cell(RSI, offset);
+                asm.call(input);
+            },
+            Instruction::JumpForwardIfZero { .. } => {
+                let body = asm.new_label();
+                let end = asm.new_label();
+                asm.cmp_cell_zero();
+                asm.jcc(EQUAL, end);
+                asm.bind(body);
+                loops.push((body, end));
+            },
+            Instruction::JumpBackwardUnlessZero { .. } => {
+                let (body, end) = loops.pop().expect("bug: jumps were checked to be balanced");
+                asm.cmp_cell_zero();
+                asm.jcc(NOT_EQUAL, body);
+                asm.bind(end);
+            },
+        }
+    }
+    exit(&mut asm, 0);
+
+    // Writes the byte at the address in RSI to stdout
+    asm.bind(output);
+    asm.mov_imm(RAX, 1);
+    asm.mov_imm(RDI, 1);
+    asm.mov_imm(RDX, 1);
+    syscall(&mut asm);
+    // cmp rax, 1
+    asm.emit(&[0x48, 0x83, 0xF8, 0x01]);
+    asm.jcc(NOT_EQUAL, output_error);
+    asm.ret();
+
+    // Reads a byte from stdin into the address in RSI
+    asm.bind(input);
+    let done = asm.new_label();
+    asm.xor_reg(RAX);
+    asm.xor_reg(RDI);
+    asm.mov_imm(RDX, 1);
+    syscall(&mut asm);
+    // test rax, rax
+    asm.emit(&[0x48, 0x85, 0xC0]);
+    asm.jcc(SIGN, input_error);
+    asm.jcc(NOT_EQUAL, done);
+    // Running out of input is not an error, it just results in zero
+    // mov byte [rsi], 0
+    asm.emit(&[0xC6, 0x06, 0x00]);
+    asm.bind(done);
+    asm.ret();
+
+    // Each error prints its message to stderr before exiting
+    let labels = [past_end, past_start, output_error, input_error];
+    for ((&label, &(_, exit_code)), &(address, len)) in labels.iter().zip(errors).zip(messages) {
+        asm.bind(label);
+        asm.mov_imm(RAX, 1);
+        asm.mov_imm(RDI, 2);
+        asm.mov_imm(RSI, address);
+        asm.mov_imm(RDX, len as u64);
+        syscall(&mut asm);
+        exit(&mut asm, exit_code);
+    }
+
+    asm.finish()
+}
+
+fn right(asm: &mut Assembler, amount: usize, options: &Options, past_end: usize) {
+    if amount >= options.tape_size {
+        asm.jmp(past_end);
+        return;
+    }
+    asm.add_rbx(amount as isize);
+    asm.cmp_reg(RBX, R14);
+    asm.jcc(ABOVE, past_end);
+}
+
+fn left(asm: &mut Assembler, amount: usize, options: &Options, past_start: usize) {
+    if amount >= options.tape_size {
+        asm.jmp(past_start);
+        return;
+    }
+    asm.add_rbx(-(amount as isize));
+    asm.cmp_reg(RBX, R13);
+    asm.jcc(BELOW, past_start);
+}
+
+fn syscall(asm: &mut Assembler) {
+    asm.emit(&[0x0F, 0x05]);
+}
+
+fn exit(asm: &mut Assembler, exit_code: i32) {
+    asm.mov_imm(RAX, 60);
+    asm.mov_imm(RDI, exit_code as u64);
+    syscall(asm);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::tests::{test_programs, expected_output, run, temp_path};
+    use super::super::super::precompile;
+    use super::super::super::Instruction::*;
+
+    use std::fs;
+    use std::process::Command;
+
+    #[test]
+    fn headers() {
+        let elf = emit(&[Increment(1), Write], &Options {tape_size: 100}).unwrap();
+        assert_eq!(&elf[..4], b"\x7FELF");
+        // Executable for x86-64
+        assert_eq!(&elf[16..20], &[2, 0, 0x3E, 0]);
+        // Two program headers right after the ELF header
+        assert_eq!(&elf[32..40], &64u64.to_le_bytes());
+        assert_eq!(&elf[56..58], &2u16.to_le_bytes());
+        // The tape takes no space in the file
+        let tape_header = &elf[64 + 56..64 + 2 * 56];
+        assert_eq!(&tape_header[16..24], &TAPE_ADDRESS.to_le_bytes());
+        assert_eq!(&tape_header[32..40], &0u64.to_le_bytes());
+        assert_eq!(&tape_header[40..48], &100u64.to_le_bytes());
+        // The whole file is loaded
+        assert_eq!(&elf[64 + 32..64 + 40], &(elf.len() as u64).to_le_bytes());
+    }
+
+    /// Runs the emitted executable (if this is a platform that can run it) and checks that it
+    /// behaves exactly like the interpreter
+    #[test]
+    fn same_as_interpreter() {
+        if !cfg!(all(target_arch = "x86_64", target_os = "linux")) {
+            return;
+        }
+
+        let exe_path = temp_path("elf-test");
+        let write_exe = |elf: Vec<u8>| {
+            use std::os::unix::fs::PermissionsExt;
+            // Remove the previous executable in case it is still being cleaned up
+            let _ = fs::remove_file(&exe_path);
+            fs::write(&exe_path, elf).unwrap();
+            fs::set_permissions(&exe_path, fs::Permissions::from_mode(0o755)).unwrap();
+        };
+
+        for (source, input, opt) in test_programs() {
+            let program = precompile(source.iter(), opt).unwrap();
+            write_exe(emit(&program, &Options::default()).unwrap());
+            assert_eq!(run(&mut Command::new(&exe_path), input), (0, expected_output(source, input)));
+        }
+
+        write_exe(emit(&[Right(5), Left(6)], &Options::default()).unwrap());
+        assert_eq!(run(&mut Command::new(&exe_path), b"").0, EXIT_OUT_OF_BOUNDS);
+    }
+}
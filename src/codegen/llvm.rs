@@ -0,0 +1,385 @@
+//! Emits a module of textual LLVM IR
+
+use super::{Options, Bounds, CodegenError, Code, check_jumps};
+use super::{EXIT_OUTPUT_ERROR, EXIT_OUT_OF_BOUNDS};
+use super::super::Instruction;
+
+/// Translates a precompiled program into an LLVM IR module (a `.ll` file) with a `main`
+/// function that reads from stdin with `getchar` and writes to stdout with `putchar`
+///
+/// The module can be run with `lli` or optimized and compiled with `opt`, `llc` or `clang`:
+///
+/// ```text
+/// clang -O3 -o program program.ll
+/// ```
+///
+/// The pointer is kept in a stack slot that `opt` promotes to a register. Since `getchar`
+/// cannot tell the end of the input apart from a read error, both result in zero.
+///
+/// # Errors
+/// Returns an error if the jumps in the program are not balanced or if the tape is too small
+/// for the offsets used by the program
+pub fn emit(program: &[Instruction], options: &Options) -> Result<String, CodegenError> {
+    check_jumps(program)?;
+    let bounds = Bounds::new(program, options)?;
+
+    let mut code = Code::new();
+    code.line("; The pointer is an index into the tape");
+    code.line(format!("@tape = internal global [{} x i8] zeroinitializer", options.tape_size));
+    let messages = [
+        ("past_end", "Moved past the end of the tape"),
+        ("past_start", "Moved past the start of the tape"),
+        ("output_error", "Could not write output"),
+    ];
+    for &(name, message) in &messages {
+        let message = format!("error: {}\n", message);
+        code.line(format!("@{}_message = private unnamed_addr constant [{} x i8] c\"{}\"",
+            name, message.len(), escape(&message)));
+    }
+    code.line("");
+    code.line("declare i32 @getchar()");
+    code.line("declare i32 @putchar(i32)");
+    code.line("declare i32 @fflush(i8*)");
+    code.line("declare i64 @write(i32, i8*, i64)");
+    code.line("declare void @exit(i32) noreturn");
+    code.line("");
+    code.line("; Writes the message to stderr and exits with the given code");
+    code.open("define private void @fail(i8* %message, i64 %length, i32 %code) noreturn {");
+    code.line("call i64 @write(i32 2, i8* %message, i64 %length)");
+    code.line("call void @exit(i32 %code)");
+    code.line("unreachable");
+    code.close("}");
+    code.line("");
+    code.open("define private i64 @right(i64 %p, i64 %amount) {");
+    code.line(format!("%room = sub i64 {}, %p", bounds.high));
+    code.line("%past = icmp uge i64 %amount, %room");
+    code.line("br i1 %past, label %error, label %ok");
+    code.outdented("error:");
+    code.line(fail(&messages[0], EXIT_OUT_OF_BOUNDS));
+    code.line("unreachable");
+    code.outdented("ok:");
+    code.line("%moved = add i64 %p, %amount");
+    code.line("ret i64 %moved");
+    code.close("}");
+    code.line("");
+    code.open("define private i64 @left(i64 %p, i64 %amount) {");
+    code.line(format!("%room = sub i64 %p, {}", bounds.low));
+    code.line("%past = icmp ugt i64 %amount, %room");
+    code.line("br i1 %past, label %error, label %ok");
+    code.outdented("error:");
+    code.line(fail(&messages[1], EXIT_OUT_OF_BOUNDS));
+    code.line("unreachable");
+    code.outdented("ok:");
+    code.line("%moved = sub i64 %p, %amount");
+    code.line("ret i64 %moved");
+    code.close("}");
+    code.line("");
+    code.open("define private void @output(i8 %value) {");
+    code.line("%c = zext i8 %value to i32");
+    code.line("%result = call i32 @putchar(i32 %c)");
+    code.line("%failed = icmp eq i32 %result, -1");
+    code.line("br i1 %failed, label %error, label %ok");
+    code.outdented("error:");
+    code.line(fail(&messages[2], EXIT_OUTPUT_ERROR));
+    code.line("unreachable");
+    code.outdented("ok:");
+    code.line("ret void");
+    code.close("}");
+    code.line("");
+    code.open("define private i8 @input() {");
+    code.line("; Make sure any prompt is visible before waiting for input");
+    code.line("call i32 @fflush(i8* null)");
+    code.line("%c = call i32 @getchar()");
+    code.line("; Running out of input is not an error, it just results in zero");
+    code.line("%eof = icmp eq i32 %c, -1");
+    code.line("%byte = trunc i32 %c to i8");
+    code.line("%value = select i1 %eof, i8 0, i8 %byte");
+    code.line("ret i8 %value");
+    code.close("}");
+    code.line("");
+    code.open("define i32 @main() {");
+    code.outdented("entry:");
+    code.line("%p = alloca i64");
+    code.line(format!("store i64 {}, i64* %p", bounds.low));
+
+    let mut main = Function {code, options, temps: 0, labels: 0};
+    let mut loops = Vec::new();
+    for &instr in program {
+        match instr {
+            Instruction::Right(amount) => main.move_pointer("right", amount),
+            Instruction::Left(amount) => main.move_pointer("left", amount),
+            Instruction::ScanRight(stride) | Instruction::ScanLeft(stride) => {
+                let label = main.label();
+                main.code.line(format!("br label %scan{}", label));
+                main.code.outdented(format!("scan{}:", label));
+                let value = main.load_cell(0);
+                let zero = main.temp();
+                main.code.line(format!("{} = icmp eq i8 {}, 0", zero, value));
+                main.code.line(format!("br i1 {}, label %scan{}.done, label %scan{}.move", zero, label, label));
+                main.code.outdented(format!("scan{}.move:", label));
+                match instr {
+                    Instruction::ScanRight(_) => main.move_pointer("right", stride),
+                    _ => main.move_pointer("left", stride),
+                }
+                main.code.line(format!("br label %scan{}", label));
+                main.code.outdented(format!("scan{}.done:", label));
+            },
+            Instruction::Increment(amount) => main.add_cell(0, amount as u8),
+            Instruction::Decrement(amount) => main.add_cell(0, (amount as u8).wrapping_neg()),
+            Instruction::Set(value) => {
+                let address = main.cell(0);
+                main.code.line(format!("store i8 {}, i8* {}", value as u8 as i8, address));
+            },
+            Instruction::MulAdd {offset, factor} => {
+                let value = main.load_cell(0);
+                let product = match factor as u8 {
+                    1 => value,
+                    factor => {
+                        let product = main.temp();
+                        main.code.line(format!("{} = mul i8 {}, {}", product, value, factor as i8));
+                        product
+                    },
+                };
+                let address = main.cell(offset);
+                let old = main.temp();
+                let new = main.temp();
+                main.code.line(format!("{} = load i8, i8* {}", old, address));
+                main.code.line(format!("{} = add i8 {}, {}", new, old, product));
+                main.code.line(format!("store i8 {}, i8* {}", new, address));
+            },
+            Instruction::AddAt {offset, delta} => main.add_cell(offset, delta as u8),
+            Instruction::Write => main.write(0),
+            Instruction::WriteAt {offset} => main.write(offset),
+            Instruction::Read => main.read(0),
+            Instruction::ReadAt {offset} => main.read(offset),
+            Instruction::JumpForwardIfZero { .. } => {
+                let label = main.label();
+                loops.push(label);
+                main.branch_unless_zero(label);
+                main.code.outdented(format!("loop{}:", label));
+            },
+            Instruction::JumpBackwardUnlessZero { .. } => {
+                let label = loops.pop().expect("bug: jumps were checked to be balanced");
+                main.branch_unless_zero(label);
+                main.code.outdented(format!("end{}:", label));
+            },
+        }
+    }
+
+    let mut code = main.code;
+    code.line("%flushed = call i32 @fflush(i8* null)");
+    code.line("%failed = icmp ne i32 %flushed, 0");
+    code.line("br i1 %failed, label %output_error, label %done");
+    code.outdented("output_error:");
+    code.line(fail(&messages[2], EXIT_OUTPUT_ERROR));
+    code.line("unreachable");
+    code.outdented("done:");
+    code.line("ret i32 0");
+    code.close("}");
+    Ok(code.finish())
+}
+
+/// Escapes the bytes of a string for an LLVM string constant
+fn escape(text: &str) -> String {
+    text.bytes().map(|byte| match byte {
+        b' '..=b'~' if byte != b'"' && byte != b'\\' => (byte as char).to_string(),
+        _ => format!("\\{:02X}", byte),
+    }).collect()
+}
+
+/// A call to `@fail` with one of the error messages
+fn fail(&(name, message): &(&str, &str), exit_code: i32) -> String {
+    let length = format!("error: {}\n", message).len();
+    format!("call void @fail(i8* getelementptr inbounds ([{0} x i8], [{0} x i8]* @{1}_message, i64 0, i64 0), i64 {0}, i32 {2})",
+        length, name, exit_code)
+}
+
+/// Keeps track of the numbered values and labels while generating the body of `@main`
+struct Function<'a> {
+    code: Code,
+    options: &'a Options,
+    temps: usize,
+    labels: usize,
+}
+
+impl<'a> Function<'a> {
+    /// A new temporary value
+    fn temp(&mut self) -> String {
+        self.temps += 1;
+        format!("%t{}", self.temps)
+    }
+
+    /// A new number for the labels of a loop
+    fn label(&mut self) -> usize {
+        self.labels += 1;
+        self.labels
+    }
+
+    /// Loads the pointer and returns the address of the cell at the given offset from it
+    fn cell(&mut self, offset: isize) -> String {
+        let mut index = self.temp();
+        self.code.line(format!("{} = load i64, i64* %p", index));
+        if offset != 0 {
+            let offset_index = self.temp();
+            self.code.line(format!("{} = add i64 {}, {}", offset_index, index, offset));
+            index = offset_index;
+        }
+        let address = self.temp();
+        self.code.line(format!("{0} = getelementptr inbounds [{1} x i8], [{1} x i8]* @tape, i64 0, i64 {2}",
+            address, self.options.tape_size, index));
+        address
+    }
+
+    fn load_cell(&mut self, offset: isize) -> String {
+        let address = self.cell(offset);
+        let value = self.temp();
+        self.code.line(format!("{} = load i8, i8* {}", value, address));
+        value
+    }
+
+    fn add_cell(&mut self, offset: isize, amount: u8) {
+        let address = self.cell(offset);
+        let old = self.temp();
+        let new = self.temp();
+        self.code.line(format!("{} = load i8, i8* {}", old, address));
+        self.code.line(format!("{} = add i8 {}, {}", new, old, amount as i8));
+        self.code.line(format!("store i8 {}, i8* {}", new, address));
+    }
+
+    /// Calls either `@right` or `@left` to move the pointer
+    fn move_pointer(&mut self, function: &str, amount: usize) {
+        let old = self.temp();
+        let new = self.temp();
+        self.code.line(format!("{} = load i64, i64* %p", old));
+        self.code.line(format!("{} = call i64 @{}(i64 {}, i64 {})", new, function, old, amount));
+        self.code.line(format!("store i64 {}, i64* %p", new));
+    }
+
+    fn write(&mut self, offset: isize) {
+        let value = self.load_cell(offset);
+        self.code.line(format!("call void @output(i8 {})", value));
+    }
+
+    fn read(&mut self, offset: isize) {
+        let value = self.temp();
+        self.code.line(format!("{} = call i8 @input()", value));
+        let address = self.cell(offset);
+        self.code.line(format!("store i8 {}, i8* {}", value, address));
+    }
+
+    /// Branches to the start of the loop if the current cell is not zero and to its end
+    /// otherwise
+    fn branch_unless_zero(&mut self, label: usize) {
+        let value = self.load_cell(0);
+        let nonzero = self.temp();
+        self.code.line(format!("{} = icmp ne i8 {}, 0", nonzero, value));
+        self.code.line(format!("br i1 {}, label %loop{}, label %end{}", nonzero, label, label));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::tests::{test_programs, expected_output, run, temp_path};
+    use super::super::super::precompile;
+    use super::super::super::Instruction::*;
+
+    use std::fs;
+    use std::process::Command;
+
+    #[test]
+    fn instructions() {
+        let code = emit(&[
+            JumpForwardIfZero {matching: None},
+            MulAdd {offset: -1, factor: -3},
+            Set(0),
+            JumpBackwardUnlessZero {matching: 1},
+            ScanRight(2),
+            AddAt {offset: 3, delta: -1},
+        ], &Options {tape_size: 100}).unwrap();
+
+        assert!(code.starts_with("\
+; The pointer is an index into the tape
+@tape = internal global [100 x i8] zeroinitializer
+@past_end_message = private unnamed_addr constant [38 x i8] c\"error: Moved past the end of the tape\\0A\"
+"));
+        assert!(code.contains("%room = sub i64 97, %p\n"));
+        assert!(code.contains("%room = sub i64 %p, 1\n"));
+        assert!(code.contains("
+entry:
+    %p = alloca i64
+    store i64 1, i64* %p
+    %t1 = load i64, i64* %p
+    %t2 = getelementptr inbounds [100 x i8], [100 x i8]* @tape, i64 0, i64 %t1
+    %t3 = load i8, i8* %t2
+    %t4 = icmp ne i8 %t3, 0
+    br i1 %t4, label %loop1, label %end1
+loop1:
+    %t5 = load i64, i64* %p
+    %t6 = getelementptr inbounds [100 x i8], [100 x i8]* @tape, i64 0, i64 %t5
+    %t7 = load i8, i8* %t6
+    %t8 = mul i8 %t7, -3
+    %t9 = load i64, i64* %p
+    %t10 = add i64 %t9, -1
+    %t11 = getelementptr inbounds [100 x i8], [100 x i8]* @tape, i64 0, i64 %t10
+    %t12 = load i8, i8* %t11
+    %t13 = add i8 %t12, %t8
+    store i8 %t13, i8* %t11
+    %t14 = load i64, i64* %p
+    %t15 = getelementptr inbounds [100 x i8], [100 x i8]* @tape, i64 0, i64 %t14
+    store i8 0, i8* %t15
+    %t16 = load i64, i64* %p
+    %t17 = getelementptr inbounds [100 x i8], [100 x i8]* @tape, i64 0, i64 %t16
+    %t18 = load i8, i8* %t17
+    %t19 = icmp ne i8 %t18, 0
+    br i1 %t19, label %loop1, label %end1
+end1:
+    br label %scan2
+scan2:
+    %t20 = load i64, i64* %p
+    %t21 = getelementptr inbounds [100 x i8], [100 x i8]* @tape, i64 0, i64 %t20
+    %t22 = load i8, i8* %t21
+    %t23 = icmp eq i8 %t22, 0
+    br i1 %t23, label %scan2.done, label %scan2.move
+scan2.move:
+    %t24 = load i64, i64* %p
+    %t25 = call i64 @right(i64 %t24, i64 2)
+    store i64 %t25, i64* %p
+    br label %scan2
+scan2.done:
+    %t26 = load i64, i64* %p
+    %t27 = add i64 %t26, 3
+    %t28 = getelementptr inbounds [100 x i8], [100 x i8]* @tape, i64 0, i64 %t27
+    %t29 = load i8, i8* %t28
+    %t30 = add i8 %t29, -1
+    store i8 %t30, i8* %t28
+    %flushed = call i32 @fflush(i8* null)
+"));
+    }
+
+    /// Runs the emitted IR with the LLVM interpreter (if it is available) and checks that it
+    /// behaves exactly like the interpreter
+    #[test]
+    fn same_as_interpreter() {
+        let source_path = temp_path("llvm-test.ll");
+        let run_ir = |code: String, input: &[u8]| -> Option<(i32, Vec<u8>)> {
+            fs::write(&source_path, code).unwrap();
+            // Check for lli before running it since `run` expects the command to exist
+            if Command::new("lli").arg("--version").output().is_err() {
+                return None;
+            }
+            Some(run(Command::new("lli").arg(&source_path), input))
+        };
+
+        for (source, input, opt) in test_programs() {
+            let program = precompile(source.iter(), opt).unwrap();
+            match run_ir(emit(&program, &Options::default()).unwrap(), input) {
+                Some(result) => assert_eq!(result, (0, expected_output(source, input))),
+                // No LLVM interpreter available to test with
+                None => return,
+            }
+        }
+
+        assert_eq!(run_ir(emit(&[Left(1)], &Options::default()).unwrap(), b"").unwrap().0, EXIT_OUT_OF_BOUNDS);
+    }
+}
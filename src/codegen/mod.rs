@@ -0,0 +1,277 @@
+//! Code generators that translate a precompiled program into source code for other languages
+//! or into a native executable
+//!
+//! Each generator has an `emit` function that takes the instructions returned by `precompile`
+//! (at any optimization level) and returns the generated code as a string (or, for `elf`, the
+//! bytes of the executable). Generating code fails with a `CodegenError` if the program does
+//! not fit in the tape given in the `Options`.
+//!
+//! Unlike `interpret`, generated programs have a tape with a fixed number of cells. The pointer
+//! starts at the first cell (leaving room for any cells addressed to its left by optimized
+//! instructions) and a generated program aborts with exit code 5 if it moves past either end
+//! of the tape.
+
+use super::Instruction;
+
+mod codegen_error;
+
+pub use self::codegen_error::*;
+
+pub mod c;
+pub mod rust;
+pub mod llvm;
+pub mod wat;
+pub mod asm;
+pub mod elf;
+
+// Exit codes used by generated programs, matching the exit codes of the command line
+// interpreter
+const EXIT_INPUT_ERROR: i32 = 2;
+const EXIT_OUTPUT_ERROR: i32 = 3;
+const EXIT_OUT_OF_BOUNDS: i32 = 5;
+
+/// Options shared by every code generator
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Options {
+    /// The number of cells in the tape of the generated program
+    pub tape_size: usize,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            tape_size: 65536,
+        }
+    }
+}
+
+/// The range of pointer positions that a generated program may use without addressing a cell
+/// outside of the tape
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Bounds {
+    /// The first position the pointer may be at (also where the pointer starts)
+    low: usize,
+    /// One past the last position the pointer may be at
+    high: usize,
+}
+
+impl Bounds {
+    /// Computes the bounds of the pointer from the offsets used by the program
+    ///
+    /// Returns an error if the tape is too small to fit the cells addressed by any single
+    /// instruction.
+    fn new(program: &[Instruction], options: &Options) -> Result<Bounds, CodegenError> {
+        let (mut left, mut right) = (0, 0);
+        for &instr in program {
+            match instr {
+                Instruction::MulAdd {offset, ..} |
+                Instruction::AddAt {offset, ..} |
+                Instruction::WriteAt {offset} |
+                Instruction::ReadAt {offset} => {
+                    if offset < 0 {
+                        left = left.max(offset.unsigned_abs());
+                    }
+                    else {
+                        right = right.max(offset as usize);
+                    }
+                },
+                _ => {},
+            }
+        }
+
+        if left + right >= options.tape_size {
+            return Err(CodegenError::TapeTooSmall {
+                tape_size: options.tape_size,
+                required: left + right + 1,
+            });
+        }
+        Ok(Bounds {low: left, high: options.tape_size - right})
+    }
+}
+
+/// Checks that every jump instruction in the program has a match so that loops can be
+/// emitted as structured blocks
+///
+/// Returns an error if the jumps in the program are not balanced. This cannot happen for a
+/// program returned by `precompile`.
+fn check_jumps(program: &[Instruction]) -> Result<(), CodegenError> {
+    let mut depth = 0usize;
+    for &instr in program {
+        match instr {
+            Instruction::JumpForwardIfZero { .. } => depth += 1,
+            Instruction::JumpBackwardUnlessZero { .. } => {
+                depth = depth.checked_sub(1).ok_or(CodegenError::MismatchedJumps)?;
+            },
+            _ => {},
+        }
+    }
+    if depth != 0 {
+        return Err(CodegenError::MismatchedJumps);
+    }
+    Ok(())
+}
+
+/// Checks that the tape has at most the given number of cells
+fn check_max_tape_size(options: &Options, max: u64) -> Result<(), CodegenError> {
+    if options.tape_size as u64 > max {
+        return Err(CodegenError::TapeTooLarge {tape_size: options.tape_size, max});
+    }
+    Ok(())
+}
+
+/// Formats a signed offset as a term that can be added to an expression (e.g. " + 2")
+fn offset_term(offset: isize) -> String {
+    if offset < 0 {
+        format!(" - {}", offset.unsigned_abs())
+    }
+    else if offset > 0 {
+        format!(" + {}", offset)
+    }
+    else {
+        String::new()
+    }
+}
+
+/// Builds up indented lines of generated code
+struct Code {
+    text: String,
+    indent: usize,
+}
+
+impl Code {
+    fn new() -> Code {
+        Code {text: String::new(), indent: 0}
+    }
+
+    /// Adds a line at the current level of indentation
+    fn line<S: AsRef<str>>(&mut self, line: S) {
+        let line = line.as_ref();
+        if !line.is_empty() {
+            for _ in 0..self.indent {
+                self.text.push_str("    ");
+            }
+        }
+        self.text.push_str(line);
+        self.text.push('\n');
+    }
+
+    /// Adds a line and indents every line after it
+    fn open<S: AsRef<str>>(&mut self, line: S) {
+        self.line(line);
+        self.indent += 1;
+    }
+
+    /// Stops indenting the lines after the matching call to `open` and adds a line
+    fn close<S: AsRef<str>>(&mut self, line: S) {
+        self.indent -= 1;
+        self.line(line);
+    }
+
+    /// Adds a line one level less indented than the current level (e.g. a label)
+    fn outdented<S: AsRef<str>>(&mut self, line: S) {
+        self.indent -= 1;
+        self.line(line);
+        self.indent += 1;
+    }
+
+    fn finish(self) -> String {
+        self.text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::Instruction::*;
+    use super::super::{interpret, precompile, OptimizationLevel, Control};
+
+    use std::env;
+    use std::io::Write;
+    use std::path::PathBuf;
+    use std::process::{Command, Stdio};
+
+    /// Programs (with their input) that the generated code of every backend is tested against
+    pub fn test_programs() -> Vec<(&'static [u8], &'static [u8], OptimizationLevel)> {
+        vec![
+            (include_bytes!("../../examples/hello-world.bf"), b"", OptimizationLevel::Off),
+            (include_bytes!("../../examples/hello-world.bf"), b"", OptimizationLevel::Maximum),
+            (include_bytes!("../../examples/sierpinski.bf"), b"", OptimizationLevel::Maximum),
+            (include_bytes!("../../examples/bsort.bf"), b"generated code\n", OptimizationLevel::Speed),
+            (include_bytes!("../../examples/qsort.bf"), b"generated code\n", OptimizationLevel::Maximum),
+            // Reads past the end of the input
+            (b",[.,]>,.", b"echo", OptimizationLevel::Maximum),
+        ]
+    }
+
+    /// The output of the unoptimized program when run by the interpreter
+    pub fn expected_output(source: &[u8], input: &[u8]) -> Vec<u8> {
+        let program = precompile(source.iter(), OptimizationLevel::Off).unwrap();
+        let mut output = Vec::new();
+        interpret(input, &mut output, program, |_| Control::Continue).unwrap();
+        output
+    }
+
+    /// Runs the command with the given input, returning its exit code and its output
+    pub fn run(command: &mut Command, input: &[u8]) -> (i32, Vec<u8>) {
+        let mut child = command.stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::null())
+            .spawn().unwrap();
+        child.stdin.take().unwrap().write_all(input).unwrap();
+        let output = child.wait_with_output().unwrap();
+        (output.status.code().unwrap(), output.stdout)
+    }
+
+    /// A path in the temporary directory that is unique to this test process
+    pub fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("brainfuck-{}-{}", ::std::process::id(), name))
+    }
+
+    #[test]
+    fn bounds() {
+        let options = Options {tape_size: 100};
+        assert_eq!(Bounds::new(&[Right(1), Increment(1)], &options), Ok(Bounds {low: 0, high: 100}));
+        assert_eq!(Bounds::new(&[
+            AddAt {offset: -3, delta: 1},
+            MulAdd {offset: 5, factor: 2},
+            WriteAt {offset: -1},
+        ], &options), Ok(Bounds {low: 3, high: 95}));
+    }
+
+    #[test]
+    fn tape_too_small() {
+        assert_eq!(Bounds::new(&[AddAt {offset: 10, delta: 1}], &Options {tape_size: 10}),
+            Err(CodegenError::TapeTooSmall {tape_size: 10, required: 11}));
+        assert_eq!(Bounds::new(&[AddAt {offset: -2, delta: 1}, WriteAt {offset: 1}], &Options {tape_size: 3}),
+            Err(CodegenError::TapeTooSmall {tape_size: 3, required: 4}));
+        assert!(Bounds::new(&[AddAt {offset: -2, delta: 1}, WriteAt {offset: 1}], &Options {tape_size: 4}).is_ok());
+
+        // Every code generator reports the error instead of panicking
+        let program = [AddAt {offset: 2, delta: 1}];
+        let options = Options {tape_size: 2};
+        let expected = CodegenError::TapeTooSmall {tape_size: 2, required: 3};
+        assert_eq!(c::emit(&program, &options).unwrap_err(), expected);
+        assert_eq!(rust::emit(&program, &options).unwrap_err(), expected);
+        assert_eq!(llvm::emit(&program, &options).unwrap_err(), expected);
+        assert_eq!(wat::emit(&program, &options).unwrap_err(), expected);
+        assert_eq!(asm::emit(&program, &options).unwrap_err(), expected);
+        assert_eq!(elf::emit(&program, &options).unwrap_err(), expected);
+    }
+
+    #[test]
+    fn tape_too_large() {
+        let options = Options {tape_size: 1 << 31};
+        let expected = CodegenError::TapeTooLarge {tape_size: 1 << 31, max: (1 << 31) - 1};
+        assert_eq!(asm::emit(&[Write], &options).unwrap_err(), expected);
+        assert_eq!(elf::emit(&[Write], &options).unwrap_err(), expected);
+        assert!(wat::emit(&[Write], &options).is_ok());
+    }
+
+    #[test]
+    fn mismatched_jumps() {
+        assert_eq!(check_jumps(&[JumpBackwardUnlessZero {matching: 0}, JumpForwardIfZero {matching: None}]),
+            Err(CodegenError::MismatchedJumps));
+        assert_eq!(check_jumps(&[JumpForwardIfZero {matching: None}]), Err(CodegenError::MismatchedJumps));
+        assert_eq!(check_jumps(&[JumpForwardIfZero {matching: None}, JumpBackwardUnlessZero {matching: 1}]), Ok(()));
+        assert_eq!(c::emit(&[JumpBackwardUnlessZero {matching: 0}], &Options::default()).unwrap_err(),
+            CodegenError::MismatchedJumps);
+    }
+}
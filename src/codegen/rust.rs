@@ -0,0 +1,218 @@
+//! Emits a standalone Rust program
+
+use super::{Options, Bounds, CodegenError, Code, check_jumps, offset_term};
+use super::{EXIT_INPUT_ERROR, EXIT_OUTPUT_ERROR, EXIT_OUT_OF_BOUNDS};
+use super::super::Instruction;
+
+/// Translates a precompiled program into the source of a Rust program (a `main.rs`) that reads
+/// from stdin and writes to stdout
+///
+/// The generated program only uses the standard library, so it can be written to a file by a
+/// build script and compiled like any other binary.
+///
+/// # Errors
+/// Returns an error if the jumps in the program are not balanced or if the tape is too small
+/// for the offsets used by the program
+pub fn emit(program: &[Instruction], options: &Options) -> Result<String, CodegenError> {
+    check_jumps(program)?;
+    let bounds = Bounds::new(program, options)?;
+
+    let mut code = Code::new();
+    code.line("// Not every program uses every helper");
+    code.line("#![allow(unused)]");
+    code.line("");
+    code.line("use std::io::{self, Read, Write};");
+    code.line("use std::process;");
+    code.line("");
+    code.line(format!("const TAPE_SIZE: usize = {};", options.tape_size));
+    code.line("// The pointer must stay in this range so that every cell it addresses is in the tape");
+    code.line(format!("const LOW: usize = {};", bounds.low));
+    code.line(format!("const HIGH: usize = {};", bounds.high));
+    code.line("");
+    code.open("struct Io {");
+    code.line("input: io::Stdin,");
+    code.line("output: io::BufWriter<io::Stdout>,");
+    code.close("}");
+    code.line("");
+    code.open("impl Io {");
+    code.open("fn fail(&mut self, message: &str, code: i32) -> ! {");
+    code.line("let _ = self.output.flush();");
+    code.line("eprintln!(\"error: {}\", message);");
+    code.line("process::exit(code);");
+    code.close("}");
+    code.line("");
+    code.open("fn write(&mut self, value: u8) {");
+    code.open("if self.output.write_all(&[value]).is_err() {");
+    code.line(format!("self.fail(\"Could not write output\", {});", EXIT_OUTPUT_ERROR));
+    code.close("}");
+    code.close("}");
+    code.line("");
+    code.open("fn read(&mut self) -> u8 {");
+    code.line("// Make sure any prompt is visible before waiting for input");
+    code.open("if self.output.flush().is_err() {");
+    code.line(format!("self.fail(\"Could not write output\", {});", EXIT_OUTPUT_ERROR));
+    code.close("}");
+    code.line("let mut byte = [0];");
+    code.open("loop {");
+    code.open("match self.input.read(&mut byte) {");
+    code.line("// Running out of input is not an error, it just results in zero");
+    code.line("Ok(0) => return 0,");
+    code.line("Ok(_) => return byte[0],");
+    code.line("Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {},");
+    code.line(format!("Err(_) => self.fail(\"Could not read input\", {}),", EXIT_INPUT_ERROR));
+    code.close("}");
+    code.close("}");
+    code.close("}");
+    code.line("");
+    code.open("fn finish(mut self) {");
+    code.open("if self.output.flush().is_err() {");
+    code.line(format!("self.fail(\"Could not write output\", {});", EXIT_OUTPUT_ERROR));
+    code.close("}");
+    code.close("}");
+    code.close("}");
+    code.line("");
+    code.open("fn right(p: usize, amount: usize, io: &mut Io) -> usize {");
+    code.open("if amount >= HIGH - p {");
+    code.line(format!("io.fail(\"Moved past the end of the tape\", {});", EXIT_OUT_OF_BOUNDS));
+    code.close("}");
+    code.line("p + amount");
+    code.close("}");
+    code.line("");
+    code.open("fn left(p: usize, amount: usize, io: &mut Io) -> usize {");
+    code.open("if amount > p - LOW {");
+    code.line(format!("io.fail(\"Moved past the start of the tape\", {});", EXIT_OUT_OF_BOUNDS));
+    code.close("}");
+    code.line("p - amount");
+    code.close("}");
+    code.line("");
+    code.open("fn main() {");
+    code.line("let mut tape = vec![0u8; TAPE_SIZE];");
+    code.line("let mut p = LOW;");
+    code.line("let mut io = Io {input: io::stdin(), output: io::BufWriter::new(io::stdout())};");
+    code.line("");
+
+    for &instr in program {
+        match instr {
+            Instruction::Right(amount) => code.line(format!("p = right(p, {}, &mut io);", amount)),
+            Instruction::Left(amount) => code.line(format!("p = left(p, {}, &mut io);", amount)),
+            Instruction::ScanRight(stride) => {
+                code.line(format!("while tape[p] != 0 {{ p = right(p, {}, &mut io); }}", stride));
+            },
+            Instruction::ScanLeft(stride) => {
+                code.line(format!("while tape[p] != 0 {{ p = left(p, {}, &mut io); }}", stride));
+            },
+            Instruction::Increment(amount) => code.line(add("p", false, (amount as u8).to_string())),
+            Instruction::Decrement(amount) => code.line(add("p", true, (amount as u8).to_string())),
+            Instruction::Set(value) => code.line(format!("tape[p] = {};", value as u8)),
+            Instruction::MulAdd {offset, factor} => {
+                let cell = format!("p{}", offset_term(offset));
+                let product = match factor.unsigned_abs() as u8 {
+                    1 => "tape[p]".to_string(),
+                    factor => format!("tape[p].wrapping_mul({})", factor),
+                };
+                code.line(add(&cell, factor < 0, product));
+            },
+            Instruction::AddAt {offset, delta} => {
+                let cell = format!("p{}", offset_term(offset));
+                code.line(add(&cell, delta < 0, (delta.unsigned_abs() as u8).to_string()));
+            },
+            Instruction::Write => code.line("io.write(tape[p]);"),
+            Instruction::WriteAt {offset} => code.line(format!("io.write(tape[p{}]);", offset_term(offset))),
+            Instruction::Read => code.line("tape[p] = io.read();"),
+            Instruction::ReadAt {offset} => code.line(format!("tape[p{}] = io.read();", offset_term(offset))),
+            Instruction::JumpForwardIfZero { .. } => code.open("while tape[p] != 0 {"),
+            Instruction::JumpBackwardUnlessZero { .. } => code.close("}"),
+        }
+    }
+
+    code.line("");
+    code.line("io.finish();");
+    code.close("}");
+    Ok(code.finish())
+}
+
+/// A statement that adds the value to (or subtracts it from) the cell at the given index with
+/// wrapping arithmetic
+fn add<S: AsRef<str>>(index: &str, subtract: bool, value: S) -> String {
+    let method = if subtract { "wrapping_sub" } else { "wrapping_add" };
+    format!("tape[{0}] = tape[{0}].{1}({2});", index, method, value.as_ref())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::tests::{test_programs, expected_output, run, temp_path};
+    use super::super::super::precompile;
+    use super::super::super::Instruction::*;
+
+    use std::fs;
+    use std::process::Command;
+
+    #[test]
+    fn instructions() {
+        let code = emit(&[
+            Right(2),
+            JumpForwardIfZero {matching: None},
+            MulAdd {offset: -1, factor: 1},
+            MulAdd {offset: 2, factor: -3},
+            Set(0),
+            JumpBackwardUnlessZero {matching: 2},
+            ScanLeft(1),
+            AddAt {offset: 3, delta: -1},
+            WriteAt {offset: 3},
+            Read,
+            Increment(300),
+        ], &Options {tape_size: 100}).unwrap();
+
+        assert!(code.contains("const TAPE_SIZE: usize = 100;\n"));
+        assert!(code.contains("const LOW: usize = 1;\n"));
+        assert!(code.contains("const HIGH: usize = 97;\n"));
+        assert!(code.ends_with("
+    p = right(p, 2, &mut io);
+    while tape[p] != 0 {
+        tape[p - 1] = tape[p - 1].wrapping_add(tape[p]);
+        tape[p + 2] = tape[p + 2].wrapping_sub(tape[p].wrapping_mul(3));
+        tape[p] = 0;
+    }
+    while tape[p] != 0 { p = left(p, 1, &mut io); }
+    tape[p + 3] = tape[p + 3].wrapping_sub(1);
+    io.write(tape[p + 3]);
+    tape[p] = io.read();
+    tape[p] = tape[p].wrapping_add(44);
+
+    io.finish();
+}
+"));
+    }
+
+    /// Compiles the emitted Rust with rustc (if it is available) and checks that it behaves
+    /// exactly like the interpreter
+    #[test]
+    fn same_as_interpreter() {
+        let source_path = temp_path("rust-test.rs");
+        let exe_path = temp_path("rust-test");
+        let compile = |code: String| -> bool {
+            fs::write(&source_path, code).unwrap();
+            // Deny warnings so that the generated code stays clean
+            let compiled = Command::new("rustc").arg("-D").arg("warnings").arg("-o").arg(&exe_path)
+                .arg(&source_path).status();
+            match compiled {
+                Ok(status) => assert!(status.success(), "rustc failed"),
+                // No compiler available to test with
+                Err(_) => return false,
+            }
+            true
+        };
+
+        for (source, input, opt) in test_programs() {
+            let program = precompile(source.iter(), opt).unwrap();
+            if !compile(emit(&program, &Options::default()).unwrap()) {
+                return;
+            }
+            assert_eq!(run(&mut Command::new(&exe_path), input), (0, expected_output(source, input)));
+        }
+
+        assert!(compile(emit(&[Right(10)], &Options {tape_size: 10}).unwrap()));
+        assert_eq!(run(&mut Command::new(&exe_path), b"").0, EXIT_OUT_OF_BOUNDS);
+    }
+}
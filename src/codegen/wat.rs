@@ -0,0 +1,424 @@
+//! Emits a WebAssembly text format (WAT) module
+
+use std::cmp;
+
+use super::{Options, Bounds, CodegenError, Code, check_jumps, check_max_tape_size};
+use super::super::Instruction;
+
+// The size of a page of WebAssembly memory
+const PAGE_SIZE: usize = 65536;
+
+/// Translates a precompiled program into a WebAssembly module in the text format
+///
+/// The module imports two functions from the host:
+///
+/// * `env.read_byte: () -> i32` returns the next byte of input or -1 if there is no more input
+/// * `env.write_byte: (i32) -> ()` outputs a byte
+///
+/// It exports its `memory`, which holds the tape, and a `run` function that runs the program.
+/// Running out of input sets the cell to zero just like in `interpret`. Moving past either end
+/// of the tape traps.
+///
+/// # Errors
+/// Returns an error if the jumps in the program are not balanced, if the tape is too small for
+/// the offsets used by the program or if the tape does not fit in 32-bit memory
+pub fn emit(program: &[Instruction], options: &Options) -> Result<String, CodegenError> {
+    check_jumps(program)?;
+    let bounds = Bounds::new(program, options)?;
+    check_max_tape_size(options, 1 << 32)?;
+    let pages = cmp::max(1, options.tape_size.div_ceil(PAGE_SIZE));
+
+    let mut code = Code::new();
+    code.open("(module");
+    code.line("(import \"env\" \"read_byte\" (func $read_byte (result i32)))");
+    code.line("(import \"env\" \"write_byte\" (func $write_byte (param i32)))");
+    code.line(format!("(memory (export \"memory\") {})", pages));
+    code.line("");
+    code.open("(func $input (result i32)");
+    code.line("(local $c i32)");
+    code.line("(local.set $c (call $read_byte))");
+    code.line(";; Running out of input is not an error, it just results in zero");
+    code.line("(select (i32.const 0) (local.get $c) (i32.lt_s (local.get $c) (i32.const 0)))");
+    code.close(")");
+    code.line("");
+    code.open("(func (export \"run\")");
+    code.line("(local $p i32)");
+    code.line(format!("(local.set $p (i32.const {}))", bounds.low));
+
+    // Every loop gets a unique label
+    let mut labels = 0;
+    let mut loops = Vec::new();
+    for &instr in program {
+        match instr {
+            Instruction::Right(amount) => right(&mut code, amount, bounds),
+            Instruction::Left(amount) => left(&mut code, amount, bounds),
+            Instruction::ScanRight(stride) | Instruction::ScanLeft(stride) => {
+                labels += 1;
+                code.open(format!("(block $scan{}_done", labels));
+                code.open(format!("(loop $scan{}", labels));
+                code.line(format!("(br_if $scan{}_done (i32.eqz {}))", labels, load(0)));
+                match instr {
+                    Instruction::ScanRight(_) => right(&mut code, stride, bounds),
+                    _ => left(&mut code, stride, bounds),
+                }
+                code.line(format!("(br $scan{})", labels));
+                code.close(")");
+                code.close(")");
+            },
+            Instruction::Increment(amount) => add(&mut code, 0, amount as u8),
+            Instruction::Decrement(amount) => add(&mut code, 0, (amount as u8).wrapping_neg()),
+            Instruction::Set(value) => code.line(format!("(i32.store8 {} (i32.const {}))", address(0), value as u8)),
+            Instruction::MulAdd {offset, factor} => {
+                code.line(format!("(i32.store8 {} (i32.add {} (i32.mul {} (i32.const {}))))",
+                    address(offset), load(offset), load(0), factor as u8));
+            },
+            Instruction::AddAt {offset, delta} => add(&mut code, offset, delta as u8),
+            Instruction::Write => code.line(format!("(call $write_byte {})", load(0))),
+            Instruction::WriteAt {offset} => code.line(format!("(call $write_byte {})", load(offset))),
+            Instruction::Read => code.line(format!("(i32.store8 {} (call $input))", address(0))),
+            Instruction::ReadAt {offset} => code.line(format!("(i32.store8 {} (call $input))", address(offset))),
+            Instruction::JumpForwardIfZero { .. } => {
+                labels += 1;
+                loops.push(labels);
+                code.open(format!("(if {}", load(0)));
+                code.open("(then");
+                code.open(format!("(loop $loop{}", labels));
+            },
+            Instruction::JumpBackwardUnlessZero { .. } => {
+                let label = loops.pop().expect("bug: jumps were checked to be balanced");
+                code.line(format!("(br_if $loop{} {})", label, load(0)));
+                code.close(")");
+                code.close(")");
+                code.close(")");
+            },
+        }
+    }
+
+    code.close(")");
+    code.close(")");
+    Ok(code.finish())
+}
+
+/// An expression for the address of the cell at the given offset from the pointer
+fn address(offset: isize) -> String {
+    if offset < 0 {
+        format!("(i32.sub (local.get $p) (i32.const {}))", offset.unsigned_abs())
+    }
+    else if offset > 0 {
+        format!("(i32.add (local.get $p) (i32.const {}))", offset)
+    }
+    else {
+        "(local.get $p)".to_string()
+    }
+}
+
+/// An expression for the value of the cell at the given offset from the pointer
+fn load(offset: isize) -> String {
+    format!("(i32.load8_u {})", address(offset))
+}
+
+fn add(code: &mut Code, offset: isize, value: u8) {
+    code.line(format!("(i32.store8 {} (i32.add {} (i32.const {})))", address(offset), load(offset), value));
+}
+
+fn right(code: &mut Code, amount: usize, bounds: Bounds) {
+    if amount >= bounds.high {
+        code.line("(unreachable)");
+        return;
+    }
+    code.line(format!("(if (i32.ge_u (i32.const {}) (i32.sub (i32.const {}) (local.get $p))) (then (unreachable)))",
+        amount, bounds.high));
+    code.line(format!("(local.set $p (i32.add (local.get $p) (i32.const {})))", amount));
+}
+
+fn left(code: &mut Code, amount: usize, bounds: Bounds) {
+    if amount >= bounds.high {
+        code.line("(unreachable)");
+        return;
+    }
+    code.line(format!("(if (i32.gt_u (i32.const {}) (i32.sub (local.get $p) (i32.const {}))) (then (unreachable)))",
+        amount, bounds.low));
+    code.line(format!("(local.set $p (i32.sub (local.get $p) (i32.const {})))", amount));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::tests::{test_programs, expected_output};
+    use super::super::super::precompile;
+    use super::super::super::Instruction::*;
+
+    use std::collections::HashMap;
+
+    #[test]
+    fn module_structure() {
+        let code = emit(&[
+            Increment(3),
+            JumpForwardIfZero {matching: None},
+            MulAdd {offset: 2, factor: -1},
+            Set(0),
+            JumpBackwardUnlessZero {matching: 2},
+            ScanLeft(2),
+            WriteAt {offset: 2},
+        ], &Options {tape_size: 70000}).unwrap();
+
+        assert!(code.starts_with("(module\n"));
+        assert!(code.contains("(import \"env\" \"read_byte\" (func $read_byte (result i32)))"));
+        assert!(code.contains("(import \"env\" \"write_byte\" (func $write_byte (param i32)))"));
+        assert!(code.contains("(memory (export \"memory\") 2)"));
+        assert!(code.contains("(func (export \"run\")"));
+        assert!(code.contains("(loop $loop1\n"));
+        assert!(code.contains("(loop $scan2\n"));
+        assert!(code.contains("(br_if $loop1 (i32.load8_u (local.get $p)))"));
+        assert!(code.contains("(i32.store8 (i32.add (local.get $p) (i32.const 2)) (i32.add (i32.load8_u (i32.add (local.get $p) (i32.const 2))) (i32.mul (i32.load8_u (local.get $p)) (i32.const 255))))"));
+
+        let open = code.matches('(').count();
+        let close = code.matches(')').count();
+        assert_eq!(open, close);
+    }
+
+    #[test]
+    fn same_as_interpreter() {
+        for (source, input, opt) in test_programs() {
+            let program = precompile(source.iter(), opt).unwrap();
+            let module = parse(&emit(&program, &Options::default()).unwrap());
+            assert_eq!(Machine::run(&module, input), Ok(expected_output(source, input)));
+        }
+
+        let module = parse(&emit(&[Left(1)], &Options::default()).unwrap());
+        assert_eq!(Machine::run(&module, b""), Err(Flow::Trap));
+    }
+
+    // A small reference interpreter for the subset of WAT emitted above. This makes it possible
+    // to test the semantics of the generated module without any WebAssembly toolchain.
+
+    #[derive(Debug, Clone, PartialEq)]
+    enum Sexp {
+        Atom(String),
+        List(Vec<Sexp>),
+    }
+
+    impl Sexp {
+        fn list(&self) -> &[Sexp] {
+            match *self {
+                Sexp::List(ref items) => items,
+                Sexp::Atom(ref atom) => panic!("expected a list, found `{}`", atom),
+            }
+        }
+
+        fn atom(&self) -> &str {
+            match *self {
+                Sexp::Atom(ref atom) => atom,
+                Sexp::List(_) => panic!("expected an atom, found a list"),
+            }
+        }
+
+        fn head(&self) -> Option<&str> {
+            match *self {
+                Sexp::List(ref items) => match items.first() {
+                    Some(Sexp::Atom(atom)) => Some(atom),
+                    _ => None,
+                },
+                Sexp::Atom(_) => None,
+            }
+        }
+    }
+
+    fn parse(text: &str) -> Sexp {
+        let mut stack = vec![Vec::new()];
+        let mut chars = text.chars().peekable();
+        while let Some(ch) = chars.next() {
+            match ch {
+                '(' => stack.push(Vec::new()),
+                ')' => {
+                    let list = stack.pop().unwrap();
+                    stack.last_mut().expect("unbalanced parens").push(Sexp::List(list));
+                },
+                ';' if chars.peek() == Some(&';') => {
+                    while chars.peek().map(|&c| c != '\n').unwrap_or(false) {
+                        chars.next();
+                    }
+                },
+                c if c.is_whitespace() => {},
+                c => {
+                    let mut atom = c.to_string();
+                    let quoted = c == '"';
+                    while let Some(&next) = chars.peek() {
+                        if quoted && atom.len() > 1 && atom.ends_with('"') {
+                            break;
+                        }
+                        if !quoted && (next.is_whitespace() || next == '(' || next == ')') {
+                            break;
+                        }
+                        atom.push(next);
+                        chars.next();
+                    }
+                    stack.last_mut().unwrap().push(Sexp::Atom(atom));
+                },
+            }
+        }
+        assert_eq!(stack.len(), 1, "unbalanced parens");
+        stack.pop().unwrap().pop().expect("empty module")
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum Flow {
+        Branch(String),
+        Trap,
+    }
+
+    struct Machine<'a> {
+        functions: HashMap<String, &'a Sexp>,
+        memory: Vec<u8>,
+        input: &'a [u8],
+        output: Vec<u8>,
+    }
+
+    impl<'a> Machine<'a> {
+        fn run(module: &'a Sexp, input: &'a [u8]) -> Result<Vec<u8>, Flow> {
+            let items = module.list();
+            assert_eq!(items[0].atom(), "module");
+            let mut machine = Machine {functions: HashMap::new(), memory: Vec::new(), input, output: Vec::new()};
+            let mut run = None;
+            for item in &items[1..] {
+                match item.head() {
+                    Some("memory") => {
+                        let pages: usize = item.list()[2].atom().parse().unwrap();
+                        machine.memory = vec![0; pages * PAGE_SIZE];
+                    },
+                    Some("func") => match item.list()[1] {
+                        Sexp::Atom(ref name) => { machine.functions.insert(name.clone(), item); },
+                        ref export => {
+                            assert_eq!(export, &parse("(export \"run\")"));
+                            run = Some(item);
+                        },
+                    },
+                    _ => {},
+                }
+            }
+
+            machine.call(run.expect("no run function"))?;
+            Ok(machine.output)
+        }
+
+        fn call(&mut self, func: &Sexp) -> Result<Option<i32>, Flow> {
+            let mut locals = HashMap::new();
+            let mut result = None;
+            for item in &func.list()[2..] {
+                match item.head() {
+                    Some("local") => { locals.insert(item.list()[1].atom().to_string(), 0); },
+                    Some("result") => {},
+                    _ => result = self.eval(item, &mut locals)?,
+                }
+            }
+            Ok(result)
+        }
+
+        fn eval_seq(&mut self, items: &[Sexp], locals: &mut HashMap<String, i32>) -> Result<(), Flow> {
+            for item in items {
+                self.eval(item, locals)?;
+            }
+            Ok(())
+        }
+
+        fn value(&mut self, expr: &Sexp, locals: &mut HashMap<String, i32>) -> Result<i32, Flow> {
+            Ok(self.eval(expr, locals)?.expect("expected a value"))
+        }
+
+        fn eval(&mut self, expr: &Sexp, locals: &mut HashMap<String, i32>) -> Result<Option<i32>, Flow> {
+            let items = expr.list();
+            let arg = |i: usize| &items[i];
+            Ok(match items[0].atom() {
+                "i32.const" => {
+                    let value: i64 = arg(1).atom().parse().unwrap();
+                    Some(value as i32)
+                },
+                "local.get" => Some(locals[arg(1).atom()]),
+                "local.set" => {
+                    let value = self.value(arg(2), locals)?;
+                    locals.insert(arg(1).atom().to_string(), value);
+                    None
+                },
+                op @ "i32.add" | op @ "i32.sub" | op @ "i32.mul" | op @ "i32.ge_u" | op @ "i32.gt_u" | op @ "i32.lt_s" => {
+                    let a = self.value(arg(1), locals)?;
+                    let b = self.value(arg(2), locals)?;
+                    Some(match op {
+                        "i32.add" => a.wrapping_add(b),
+                        "i32.sub" => a.wrapping_sub(b),
+                        "i32.mul" => a.wrapping_mul(b),
+                        "i32.ge_u" => ((a as u32) >= (b as u32)) as i32,
+                        "i32.gt_u" => ((a as u32) > (b as u32)) as i32,
+                        _ => (a < b) as i32,
+                    })
+                },
+                "i32.eqz" => Some((self.value(arg(1), locals)? == 0) as i32),
+                "i32.load8_u" => {
+                    let address = self.value(arg(1), locals)? as u32 as usize;
+                    Some(*self.memory.get(address).ok_or(Flow::Trap)? as i32)
+                },
+                "i32.store8" => {
+                    let address = self.value(arg(1), locals)? as u32 as usize;
+                    let value = self.value(arg(2), locals)?;
+                    *self.memory.get_mut(address).ok_or(Flow::Trap)? = value as u8;
+                    None
+                },
+                "select" => {
+                    let a = self.value(arg(1), locals)?;
+                    let b = self.value(arg(2), locals)?;
+                    Some(if self.value(arg(3), locals)? != 0 { a } else { b })
+                },
+                "call" => match arg(1).atom() {
+                    "$read_byte" => Some(match self.input.split_first() {
+                        Some((&byte, rest)) => {
+                            self.input = rest;
+                            byte as i32
+                        },
+                        None => -1,
+                    }),
+                    "$write_byte" => {
+                        let value = self.value(arg(2), locals)?;
+                        self.output.push(value as u8);
+                        None
+                    },
+                    name => {
+                        let func = self.functions[name];
+                        self.call(func)?
+                    },
+                },
+                "if" => {
+                    if self.value(arg(1), locals)? != 0 {
+                        assert_eq!(arg(2).head(), Some("then"));
+                        self.eval_seq(&arg(2).list()[1..], locals)?;
+                    }
+                    None
+                },
+                "block" => {
+                    match self.eval_seq(&items[2..], locals) {
+                        Err(Flow::Branch(ref label)) if label == arg(1).atom() => {},
+                        result => result?,
+                    }
+                    None
+                },
+                "loop" => {
+                    loop {
+                        match self.eval_seq(&items[2..], locals) {
+                            Err(Flow::Branch(ref label)) if label == arg(1).atom() => continue,
+                            result => result?,
+                        }
+                        break;
+                    }
+                    None
+                },
+                "br" => return Err(Flow::Branch(arg(1).atom().to_string())),
+                "br_if" => {
+                    if self.value(arg(2), locals)? != 0 {
+                        return Err(Flow::Branch(arg(1).atom().to_string()));
+                    }
+                    None
+                },
+                "unreachable" => return Err(Flow::Trap),
+                op => panic!("unsupported instruction: {}", op),
+            })
+        }
+    }
+}
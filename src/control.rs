@@ -0,0 +1,14 @@
+/// What the interpreter does after the callback it was given returns
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Control {
+    /// Keep running the program
+    #[default]
+    Continue,
+    /// Stop running the program for good, as if it had finished
+    Break,
+    /// Stop running the program so that it can be resumed later
+    ///
+    /// Only an `Interpreter` can be resumed. `interpret` has nothing to resume, so it treats
+    /// this the same as `Break`.
+    Pause,
+}
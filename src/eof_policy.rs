@@ -0,0 +1,45 @@
+use std::str::FromStr;
+
+/// What a "," instruction does once there is no more input left to read
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum EofPolicy {
+    /// Set the cell to zero
+    #[default]
+    Zero,
+    /// Leave the cell unchanged
+    Unchanged,
+    /// Set the cell to its largest value (e.g. 255 for 8-bit cells), which is the same as
+    /// setting it to -1
+    MaxValue,
+    /// Stop running the program with `RuntimeError::EndOfInput`
+    Error,
+}
+
+impl FromStr for EofPolicy {
+    type Err = ();
+
+    fn from_str(val: &str) -> Result<Self, Self::Err> {
+        match val {
+            "zero" => Ok(EofPolicy::Zero),
+            "unchanged" => Ok(EofPolicy::Unchanged),
+            "max" => Ok(EofPolicy::MaxValue),
+            "error" => Ok(EofPolicy::Error),
+            _ => Err(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_str() {
+        assert_eq!("zero".parse(), Ok(EofPolicy::Zero));
+        assert_eq!("unchanged".parse(), Ok(EofPolicy::Unchanged));
+        assert_eq!("max".parse(), Ok(EofPolicy::MaxValue));
+        assert_eq!("error".parse(), Ok(EofPolicy::Error));
+
+        assert!("foo".parse::<EofPolicy>().is_err());
+    }
+}
@@ -0,0 +1,20 @@
+use std::fmt;
+use std::time::Duration;
+
+/// A limit placed on how long a program may run, see `InterpreterConfig`
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ExecutionLimit {
+    /// The most instructions the program may run
+    Steps(u64),
+    /// The most time the program may run for
+    Timeout(Duration),
+}
+
+impl fmt::Display for ExecutionLimit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ExecutionLimit::Steps(steps) => write!(f, "{} steps", steps),
+            ExecutionLimit::Timeout(timeout) => write!(f, "{:?}", timeout),
+        }
+    }
+}
@@ -0,0 +1,315 @@
+use super::{ParseError, OptimizationLevel, precompile};
+
+/// Options for `format_source`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormatOptions {
+    /// The maximum length of a line, including its indentation
+    ///
+    /// Only a single word of a comment that is longer than this is put on a line by itself
+    /// rather than being split.
+    pub line_width: usize,
+    /// Keep comments (with their whitespace collapsed) instead of removing them
+    pub comments: bool,
+    /// Follow each run of more than three of the same instruction with its length, e.g.
+    /// `+++++ (5)`
+    pub annotate_runs: bool,
+}
+
+impl Default for FormatOptions {
+    fn default() -> FormatOptions {
+        FormatOptions {
+            line_width: 80,
+            comments: true,
+            annotate_runs: false,
+        }
+    }
+}
+
+// The indentation of each level of loop nesting
+const INDENT: &str = "    ";
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+enum Token {
+    // A run of the same instruction
+    Code(char, usize),
+    // A word of a comment (or an annotation)
+    Word(String),
+    Open,
+    Close,
+}
+
+/// Reformats the source of a program with the body of each loop indented on its own lines
+///
+/// Loops that contain no other loops and fit on a line are kept on a single line (e.g.
+/// `[->+<]`). Formatting only changes the comments and whitespace around the instructions, so
+/// the formatted source precompiles to exactly the same instructions as the original.
+///
+/// Returns an error if the jumps in the program are not balanced since it cannot be indented.
+pub fn format_source<'a, I>(bytes: I, options: &FormatOptions) -> Result<String, ParseError>
+    where I: IntoIterator<Item=&'a u8> {
+    let bytes: Vec<u8> = bytes.into_iter().cloned().collect();
+    precompile(bytes.iter(), OptimizationLevel::Off)?;
+
+    let tokens = tokenize(&bytes, options);
+    let mut layout = Layout {
+        text: String::new(),
+        line: String::new(),
+        depth: 0,
+        width: options.line_width,
+        after_word: false,
+    };
+
+    let mut i = 0;
+    while i < tokens.len() {
+        match tokens[i] {
+            Token::Code(instr, n) => {
+                layout.code(&instr.to_string().repeat(n));
+                if options.annotate_runs && n > 3 {
+                    layout.word(&format!("({})", n));
+                }
+            },
+            Token::Word(ref word) => layout.word(word),
+            Token::Open => {
+                if let Some((inline, len)) = inline_loop(&tokens[i..]) {
+                    if inline.len() <= layout.available() {
+                        layout.code(&inline);
+                        i += len;
+                        continue;
+                    }
+                }
+                layout.line_by_itself("[");
+                layout.depth += 1;
+            },
+            Token::Close => {
+                layout.finish_line();
+                layout.depth -= 1;
+                layout.line_by_itself("]");
+            },
+        }
+        i += 1;
+    }
+    layout.finish_line();
+
+    Ok(layout.text)
+}
+
+/// Splits the source into runs of instructions and words of comments
+fn tokenize(bytes: &[u8], options: &FormatOptions) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut comment = Vec::new();
+    for &byte in bytes {
+        let instr = match byte {
+            b'+' | b'-' | b'<' | b'>' | b'.' | b',' | b'[' | b']' => byte as char,
+            _ => {
+                comment.push(byte);
+                continue;
+            },
+        };
+
+        if options.comments {
+            let text = String::from_utf8_lossy(&comment).into_owned();
+            tokens.extend(text.split_whitespace().map(|word| Token::Word(word.to_owned())));
+        }
+        comment.clear();
+
+        match instr {
+            '[' => tokens.push(Token::Open),
+            ']' => tokens.push(Token::Close),
+            _ => match tokens.last_mut() {
+                // Runs are continued across removed comments and whitespace
+                Some(&mut Token::Code(last, ref mut n)) if last == instr => *n += 1,
+                _ => tokens.push(Token::Code(instr, 1)),
+            },
+        }
+    }
+    if options.comments {
+        let text = String::from_utf8_lossy(&comment).into_owned();
+        tokens.extend(text.split_whitespace().map(|word| Token::Word(word.to_owned())));
+    }
+    tokens
+}
+
+/// If the loop at the start of the tokens contains only instructions, returns the loop as a
+/// single string along with the number of tokens it spans
+fn inline_loop(tokens: &[Token]) -> Option<(String, usize)> {
+    let mut inline = String::from("[");
+    for (i, token) in tokens.iter().enumerate().skip(1) {
+        match *token {
+            Token::Code(instr, n) => inline.push_str(&instr.to_string().repeat(n)),
+            Token::Close => {
+                inline.push(']');
+                return Some((inline, i + 1));
+            },
+            Token::Open | Token::Word(_) => return None,
+        }
+    }
+    None
+}
+
+/// Fills lines with instructions and words at the indentation of the current loop
+struct Layout {
+    text: String,
+    line: String,
+    depth: usize,
+    width: usize,
+    // Words need to be separated from whatever comes before them by a space
+    after_word: bool,
+}
+
+impl Layout {
+    /// The number of characters left on the current line
+    fn available(&self) -> usize {
+        self.width.saturating_sub(INDENT.len() * self.depth + self.line.len())
+    }
+
+    fn code(&mut self, code: &str) {
+        if self.after_word {
+            self.space_or_wrap(code.len());
+        }
+        else if code.len() > self.available() {
+            self.finish_line();
+        }
+        self.after_word = false;
+
+        // Instructions can be split over lines without changing their meaning
+        let mut code = code;
+        while code.len() > self.available() {
+            let split = self.available().max(1);
+            self.line.push_str(&code[..split]);
+            self.finish_line();
+            code = &code[split..];
+        }
+        self.line.push_str(code);
+    }
+
+    fn word(&mut self, word: &str) {
+        self.space_or_wrap(word.len());
+        self.line.push_str(word);
+        self.after_word = true;
+    }
+
+    /// Separates the next piece of text with a space or puts it on the next line if it does
+    /// not fit on this one
+    fn space_or_wrap(&mut self, len: usize) {
+        if self.line.is_empty() {
+            return;
+        }
+        if len + 1 > self.available() {
+            self.finish_line();
+        }
+        else {
+            self.line.push(' ');
+        }
+    }
+
+    fn line_by_itself(&mut self, line: &str) {
+        self.finish_line();
+        self.line.push_str(line);
+        self.finish_line();
+    }
+
+    fn finish_line(&mut self) {
+        if self.line.is_empty() {
+            return;
+        }
+        for _ in 0..self.depth {
+            self.text.push_str(INDENT);
+        }
+        self.text.push_str(&self.line);
+        self.text.push('\n');
+        self.line.clear();
+        self.after_word = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn indentation() {
+        let source = b"hello ++++++++[>++++[>++>+++<<-]>+[<. loop [-]]<-]>>. world";
+        assert_eq!(format_source(source.iter(), &FormatOptions::default()).unwrap(), "\
+hello ++++++++
+[
+    >++++[>++>+++<<-]>+
+    [
+        <. loop [-]
+    ]
+    <-
+]
+>>. world
+");
+        let options = FormatOptions {comments: false, ..FormatOptions::default()};
+        assert_eq!(format_source(source.iter(), &options).unwrap(), "\
+++++++++
+[
+    >++++[>++>+++<<-]>+
+    [
+        <.[-]
+    ]
+    <-
+]
+>>.
+");
+    }
+
+    #[test]
+    fn line_width() {
+        let options = FormatOptions {line_width: 10, ..FormatOptions::default()};
+        let source = b"a comment +++++ +++++ +++++ [-] [ > [-] ] with a verylongword";
+        assert_eq!(format_source(source.iter(), &options).unwrap(), "\
+a comment
+++++++++++
++++++[-]
+[
+    >[-]
+]
+with a
+verylongword
+");
+    }
+
+    #[test]
+    fn annotations() {
+        let options = FormatOptions {annotate_runs: true, ..FormatOptions::default()};
+        assert_eq!(format_source(b"++++>>>--[-]<<<<<".iter(), &options).unwrap(), "++++ (4) >>>--[-]<<<<< (5)\n");
+    }
+
+    #[test]
+    fn mismatched_jumps() {
+        assert!(format_source(b"[[]".iter(), &FormatOptions::default()).is_err());
+    }
+
+    #[test]
+    fn same_instructions() {
+        let sources: &[&[u8]] = &[
+            include_bytes!("../examples/hello-world.bf"),
+            include_bytes!("../examples/99bottles.bf"),
+            include_bytes!("../examples/bsort.bf"),
+            include_bytes!("../examples/mandel.bf"),
+        ];
+        let options = [
+            FormatOptions::default(),
+            FormatOptions {line_width: 1, comments: false, annotate_runs: false},
+            FormatOptions {line_width: 40, comments: true, annotate_runs: true},
+        ];
+        let levels = [OptimizationLevel::Off, OptimizationLevel::Maximum, OptimizationLevel::Size];
+        for &source in sources {
+            for options in &options {
+                let formatted = format_source(source.iter(), options).unwrap();
+                for &opt in &levels {
+                    assert_eq!(precompile(formatted.as_bytes(), opt), precompile(source.iter(), opt));
+                }
+                // Only deeply nested code or a single long word can go past the line width
+                assert!(formatted.lines().all(|line| line.len() <= options.line_width
+                    || !line.trim_start().contains(' ')));
+
+                // Annotations become comments, so they would be repeated
+                if !options.annotate_runs {
+                    assert_eq!(format_source(formatted.as_bytes(), options).unwrap(), formatted);
+                }
+            }
+        }
+    }
+}
@@ -26,6 +26,40 @@ pub enum Instruction {
         // Strictly initialized when the program is loaded into memory
         matching: usize,
     },
+    // "[-]" - set the byte at the pointer to the given value
+    // Only produced by optimizations. The value wraps around just like incrementing and
+    // decrementing would.
+    Set(isize),
+    // "[->+<]" - add the byte at the pointer multiplied by factor to the byte at the given offset
+    // from the pointer
+    // Only produced by optimizations. Does nothing if the byte at the pointer is zero.
+    MulAdd {
+        offset: isize,
+        factor: isize,
+    },
+    // "[>]" - move the pointer right by the given stride until the byte at the pointer is zero
+    // Only produced by optimizations
+    ScanRight(usize),
+    // "[<]" - move the pointer left by the given stride until the byte at the pointer is zero
+    // Only produced by optimizations
+    ScanLeft(usize),
+    // ">+<" - add delta to the byte at the given offset from the pointer
+    // Only produced by optimizations. The delta wraps around just like incrementing and
+    // decrementing would.
+    AddAt {
+        offset: isize,
+        delta: isize,
+    },
+    // ">.<" - output the byte at the given offset from the pointer
+    // Only produced by optimizations
+    WriteAt {
+        offset: isize,
+    },
+    // ">,<" - input a byte and store it in the byte at the given offset from the pointer
+    // Only produced by optimizations
+    ReadAt {
+        offset: isize,
+    },
 }
 
 impl fmt::Display for Instruction {
@@ -39,6 +73,13 @@ impl fmt::Display for Instruction {
             Instruction::Read => ",".to_owned(),
             Instruction::JumpForwardIfZero { .. } => "[".to_owned(),
             Instruction::JumpBackwardUnlessZero { .. } => "]".to_owned(),
+            Instruction::Set(value) => format!("set({})", value),
+            Instruction::MulAdd {offset, factor} => format!("mul({}, {})", offset, factor),
+            Instruction::ScanRight(n) => format!("scan({})", n),
+            Instruction::ScanLeft(n) => format!("scan(-{})", n),
+            Instruction::AddAt {offset, delta} => format!("add({}, {})", offset, delta),
+            Instruction::WriteAt {offset} => format!("write({})", offset),
+            Instruction::ReadAt {offset} => format!("read({})", offset),
         }.as_ref())
     }
 }
@@ -75,5 +116,22 @@ mod tests {
 
         assert_eq!(Instruction::JumpForwardIfZero {matching: None}.to_string(), "[");
         assert_eq!(Instruction::JumpBackwardUnlessZero {matching: 0}.to_string(), "]");
+
+        assert_eq!(Instruction::Set(0).to_string(), "set(0)");
+        assert_eq!(Instruction::Set(5).to_string(), "set(5)");
+        assert_eq!(Instruction::Set(-1).to_string(), "set(-1)");
+
+        assert_eq!(Instruction::MulAdd {offset: 2, factor: 3}.to_string(), "mul(2, 3)");
+        assert_eq!(Instruction::MulAdd {offset: -1, factor: -1}.to_string(), "mul(-1, -1)");
+
+        assert_eq!(Instruction::ScanRight(1).to_string(), "scan(1)");
+        assert_eq!(Instruction::ScanRight(3).to_string(), "scan(3)");
+        assert_eq!(Instruction::ScanLeft(1).to_string(), "scan(-1)");
+        assert_eq!(Instruction::ScanLeft(3).to_string(), "scan(-3)");
+
+        assert_eq!(Instruction::AddAt {offset: 2, delta: 3}.to_string(), "add(2, 3)");
+        assert_eq!(Instruction::AddAt {offset: -1, delta: -4}.to_string(), "add(-1, -4)");
+        assert_eq!(Instruction::WriteAt {offset: 2}.to_string(), "write(2)");
+        assert_eq!(Instruction::ReadAt {offset: -3}.to_string(), "read(-3)");
     }
 }
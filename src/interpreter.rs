@@ -1,93 +1,541 @@
-use std::io::{Read, Write};
+use std::io::{self, Read, Write};
 use std::collections::VecDeque;
+use std::time::Instant;
 
-use super::Instruction;
+use super::{Instruction, RuntimeError, Cell, Control, EofPolicy, ExecutionLimit, InterpreterConfig, TapeConfig, TapeBoundary, DEFAULT_TAPE_SIZE};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub struct InterpreterState<'a> {
+pub struct InterpreterState<'a, C: 'a + Cell = u8> {
     /// index in the program of the instruction that was just run
     /// Note that this instruction is computed *after* precompilation and so it may not
-    /// match the program file exactly
+    /// match the program file exactly. Use the `SourceMap` from `precompile_with_source_map`
+    /// to find where this instruction came from in the source.
     pub current_instruction: usize,
     /// The instruction that was just run
     pub instruction: Instruction,
     /// The current "pointer" value that represents the current cell in memory
     pub current_pointer: usize,
     /// The entire memory buffer (read-only)
-    pub memory: &'a VecDeque<u8>,
+    pub memory: &'a VecDeque<C>,
 }
 
-/// callback is called after each instruction
-pub fn interpret<I, O, F>(mut inp: I, mut out: O, mut program: Vec<Instruction>, mut callback: F)
-    where I: Read, O: Write,
-          F: FnMut(InterpreterState) {
+/// Information about a program that ran to completion
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExecutionSummary {
+    /// The number of instructions that were run
+    pub steps: u64,
+    /// The value of the pointer when the program finished
+    pub final_pointer: usize,
+}
 
-    let mut buffer: VecDeque<u8> = VecDeque::new();
-    // Make sure there is at least one cell to begin with
-    buffer.push_back(0u8);
+/// A program being run one instruction at a time
+///
+/// The interpreter owns the program, its input and output and the tape of cells of type `C`
+/// (see `Cell`). Running stops whenever the caller wants it to and continues from the same
+/// place the next time it runs, so debuggers and schedulers can drive a program
+/// incrementally.
+///
+/// If an instruction fails, the interpreter stays at that instruction without changing the
+/// tape, so running again retries it.
+pub struct Interpreter<I, O, C: Cell = u8> {
+    inp: I,
+    out: O,
+    program: Vec<Instruction>,
+    config: InterpreterConfig,
+    tape: Tape<C>,
+    // index in the program of the next instruction to run
+    next_instruction: usize,
+    steps: u64,
+    // when the timeout runs out, measured from when the interpreter was created, or None if it
+    // is too far in the future to represent
+    deadline: Option<Instant>,
+    // true if there is any limit to check before each instruction
+    limited: bool,
+    // true once a callback has stopped the program with Control::Break
+    halted: bool,
+}
 
-    // pointer is the position "pointer" in the buffer
-    let mut pointer: usize = 0;
-    // next_instruction is the instruction index in the program
-    let mut next_instruction: usize = 0;
+impl<I: Read, O: Write, C: Cell> Interpreter<I, O, C> {
+    /// Creates an interpreter that will run the program from its first instruction
+    pub fn new(inp: I, out: O, program: Vec<Instruction>) -> Interpreter<I, O, C> {
+        Interpreter::with_config(inp, out, program, InterpreterConfig::default())
+    }
 
-    loop {
-        if next_instruction >= program.len() {
-            break;
+    /// Creates an interpreter that will run the program with the given configuration
+    pub fn with_config(inp: I, out: O, program: Vec<Instruction>, config: InterpreterConfig) -> Interpreter<I, O, C> {
+        Interpreter {
+            inp,
+            out,
+            program,
+            tape: Tape::new(&config.tape),
+            next_instruction: 0,
+            steps: 0,
+            deadline: config.timeout.and_then(|timeout| Instant::now().checked_add(timeout)),
+            limited: config.max_steps.is_some() || config.timeout.is_some(),
+            halted: false,
+            config,
         }
-        let current_instruction = next_instruction;
-        let mut instr = program[current_instruction];
-        next_instruction += 1;
+    }
+
+    /// The program being run
+    ///
+    /// Jump instructions are filled in as they are run, so they may not match the program
+    /// that the interpreter was created with.
+    pub fn program(&self) -> &[Instruction] {
+        &self.program
+    }
+
+    /// The entire memory buffer
+    pub fn memory(&self) -> &VecDeque<C> {
+        &self.tape.buffer
+    }
+
+    /// The position of the pointer in the memory buffer
+    pub fn pointer(&self) -> usize {
+        self.tape.pointer
+    }
+
+    /// The index in the program of the next instruction to run
+    pub fn next_instruction(&self) -> usize {
+        self.next_instruction
+    }
+
+    /// The number of instructions that have been run
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Returns true if the program has run to completion or was stopped with `Control::Break`
+    pub fn is_finished(&self) -> bool {
+        self.halted || self.next_instruction >= self.program.len()
+    }
+
+    /// The output of the program so far
+    pub fn output(&self) -> &O {
+        &self.out
+    }
+
+    /// The number of steps and the final pointer if the program has finished
+    pub fn summary(&self) -> Option<ExecutionSummary> {
+        if self.is_finished() {
+            Some(ExecutionSummary {
+                steps: self.steps,
+                final_pointer: self.tape.pointer,
+            })
+        }
+        else {
+            None
+        }
+    }
+
+    /// Runs the next instruction and returns the state right after it, or None if the program
+    /// has already finished
+    pub fn step(&mut self) -> Result<Option<InterpreterState<'_, C>>, RuntimeError> {
+        let mut ran = None;
+        self.run_until(|state| {
+            ran = Some((state.current_instruction, state.instruction));
+            true
+        })?;
+        Ok(ran.map(move |(current_instruction, instr)| self.state(current_instruction, instr)))
+    }
+
+    /// Runs at most the given number of instructions, returning a summary if the program
+    /// finished
+    pub fn run(&mut self, steps: u64) -> Result<Option<ExecutionSummary>, RuntimeError> {
+        if steps == 0 {
+            return Ok(self.summary());
+        }
+        let mut left = steps;
+        self.run_until(|_| {
+            left -= 1;
+            left == 0
+        })
+    }
+
+    /// Runs instructions until the predicate returns true for the state after one of them,
+    /// returning a summary if the program finished instead
+    #[inline(always)]
+    pub fn run_until<P>(&mut self, mut predicate: P) -> Result<Option<ExecutionSummary>, RuntimeError>
+        where P: FnMut(InterpreterState<C>) -> bool {
+        self.run_with(|state| if predicate(state) { Control::Pause } else { Control::Continue })
+    }
+
+    /// Runs instructions, calling the callback after each one, until the callback returns
+    /// `Control::Pause` or `Control::Break`
+    ///
+    /// Returns a summary if the program finished or the callback stopped it with
+    /// `Control::Break`, which means nothing more will run. After `Control::Pause`, the program
+    /// continues from the next instruction the next time the interpreter runs.
+    #[inline(always)]
+    pub fn run_with<F>(&mut self, mut callback: F) -> Result<Option<ExecutionSummary>, RuntimeError>
+        where F: FnMut(InterpreterState<C>) -> Control {
+        if self.halted {
+            return Ok(self.summary());
+        }
+
+        while self.next_instruction < self.program.len() {
+            if self.limited {
+                self.check_limits()?;
+            }
+
+            let current_instruction = self.next_instruction;
+            // Running a jump may fill in its match, but the state shows the jump that was run
+            let instr = self.program[current_instruction];
+            self.next_instruction = self.run_instruction(current_instruction, instr)?;
+            self.steps += 1;
+
+            match callback(self.state(current_instruction, instr)) {
+                Control::Continue => {},
+                Control::Break => {
+                    self.halted = true;
+                    break;
+                },
+                Control::Pause => break,
+            }
+        }
+        Ok(self.summary())
+    }
+
+    /// Returns an error if the program has run past one of its limits
+    fn check_limits(&self) -> Result<(), RuntimeError> {
+        match exceeded_limit(&self.config, self.deadline, self.steps) {
+            Some(limit) => Err(RuntimeError::LimitExceeded {
+                limit,
+                instruction: self.next_instruction,
+                pointer: self.tape.pointer,
+                steps: self.steps,
+            }),
+            None => Ok(()),
+        }
+    }
 
+    /// The state right after the given instruction was run
+    #[inline(always)]
+    fn state(&self, current_instruction: usize, instr: Instruction) -> InterpreterState<'_, C> {
+        InterpreterState {
+            current_instruction,
+            instruction: instr,
+            current_pointer: self.tape.pointer,
+            memory: &self.tape.buffer,
+        }
+    }
+
+    /// Runs the given instruction at the given index, returning the index of the instruction
+    /// to run after it
+    #[inline(always)]
+    fn run_instruction(&mut self, current_instruction: usize, instr: Instruction) -> Result<usize, RuntimeError> {
+        let mut next_instruction = current_instruction + 1;
+        let tape = &mut self.tape;
+        let out_of_bounds = || RuntimeError::OutOfBounds {instruction: current_instruction};
+        let pointer = tape.pointer;
         match instr {
-            Instruction::Right(amount) => {
-                pointer += amount;
-                while pointer >= buffer.len() {
-                    buffer.push_back(0u8);
+            Instruction::Right(amount) => tape.move_by(amount as isize).ok_or_else(out_of_bounds)?,
+            Instruction::Left(amount) => tape.move_by(-(amount as isize)).ok_or_else(out_of_bounds)?,
+            Instruction::ScanRight(stride) | Instruction::ScanLeft(stride) => {
+                let stride = if let Instruction::ScanRight(_) = instr { stride as isize } else { -(stride as isize) };
+                // The loop this replaced would never have ended, so neither does this
+                if !tape.scan(stride).ok_or_else(out_of_bounds)? {
+                    next_instruction = current_instruction;
                 }
             },
-            Instruction::Left(amount) => {
-                if amount > pointer {
-                    for _ in 0..(amount - pointer) {
-                        buffer.push_front(0u8);
-                    }
-                    pointer = 0;
+            Instruction::Increment(amount) => tape.buffer[pointer] = tape.buffer[pointer].wrapping_add(C::wrap(amount as isize)),
+            Instruction::Decrement(amount) => tape.buffer[pointer] = tape.buffer[pointer].wrapping_sub(C::wrap(amount as isize)),
+            Instruction::Set(value) => tape.buffer[pointer] = C::wrap(value),
+            Instruction::MulAdd {offset, factor} => {
+                let value = tape.buffer[pointer];
+                // The loop this replaced would not have run at all (or moved the pointer)
+                if value != C::default() {
+                    let target = tape.cell_at(offset).ok_or_else(out_of_bounds)?;
+                    tape.buffer[target] = tape.buffer[target].wrapping_add(value.wrapping_mul(C::wrap(factor)));
                 }
-                else {
-                    pointer -= amount;
+            },
+            Instruction::Write => self.out.write_all(&[tape.buffer[pointer].low_byte()]).map_err(RuntimeError::OutputError)?,
+            Instruction::Read => read_cell(&mut self.inp, &mut tape.buffer[pointer], self.config.eof, current_instruction)?,
+            Instruction::AddAt {offset, delta} => {
+                let target = tape.cell_at(offset).ok_or_else(out_of_bounds)?;
+                tape.buffer[target] = tape.buffer[target].wrapping_add(C::wrap(delta));
+            },
+            Instruction::WriteAt {offset} => {
+                let target = tape.cell_at(offset).ok_or_else(out_of_bounds)?;
+                self.out.write_all(&[tape.buffer[target].low_byte()]).map_err(RuntimeError::OutputError)?;
+            },
+            Instruction::ReadAt {offset} => {
+                let target = tape.cell_at(offset).ok_or_else(out_of_bounds)?;
+                read_cell(&mut self.inp, &mut tape.buffer[target], self.config.eof, current_instruction)?;
+            },
+            Instruction::JumpForwardIfZero {matching} => {
+                if tape.buffer[pointer] == C::default() {
+                    next_instruction = match matching {
+                        Some(matching) => matching,
+                        None => fill_matching(&mut self.program, current_instruction)?,
+                    };
                 }
             },
-            Instruction::Increment(amount) => buffer[pointer] = buffer[pointer].wrapping_add(amount as u8),
-            Instruction::Decrement(amount) => buffer[pointer] = buffer[pointer].wrapping_sub(amount as u8),
-            Instruction::Write => out.write_all(&[buffer[pointer]]).expect("Could not output"),
-            Instruction::Read => {
-                let mut inbuffer: [u8; 1] = [0];
-                let res = inp.read_exact(&mut inbuffer[0..1]);
-                if res.is_ok() {
-                    buffer[pointer] = inbuffer[0];
+            Instruction::JumpBackwardUnlessZero {matching} => {
+                if tape.buffer[pointer] != C::default() {
+                    next_instruction = matching;
+                }
+            },
+        }
+        Ok(next_instruction)
+    }
+}
+
+/// callback is called after each instruction and decides whether the program keeps running
+/// (see `Control`)
+///
+/// The cells of the tape are 8 bits wide. Use `interpret_with_config` to run a program with
+/// wider cells or `Interpreter` to run it a few instructions at a time.
+pub fn interpret<I, O, F>(inp: I, out: O, program: Vec<Instruction>, callback: F) -> Result<ExecutionSummary, RuntimeError>
+    where I: Read, O: Write,
+          F: FnMut(InterpreterState) -> Control {
+    interpret_with_config(inp, out, program, &InterpreterConfig::default(), callback)
+}
+
+/// Same as `interpret`, but runs the program with the given configuration
+///
+/// The cells of the tape have the type `C` (see `Cell`), which usually needs to be given
+/// explicitly, e.g. `interpret_with_config::<u16, _, _, _>(...)`.
+pub fn interpret_with_config<C, I, O, F>(inp: I, out: O, program: Vec<Instruction>, config: &InterpreterConfig, mut callback: F) -> Result<ExecutionSummary, RuntimeError>
+    where C: Cell, I: Read, O: Write,
+          F: FnMut(InterpreterState<C>) -> Control {
+    let mut interpreter = Interpreter::with_config(inp, out, program, config.clone());
+    // There is no way to resume a paused program once this returns
+    let summary = interpreter.run_with(|state| match callback(state) {
+        Control::Pause => Control::Break,
+        control => control,
+    })?;
+    Ok(summary.expect("bug: program stopped before it finished"))
+}
+
+/// Returns the limit that the program has run past after running the given number of steps,
+/// if any
+// `u64::is_multiple_of` needs a newer compiler than the rest of the crate
+#[allow(clippy::manual_is_multiple_of)]
+fn exceeded_limit(config: &InterpreterConfig, deadline: Option<Instant>, steps: u64) -> Option<ExecutionLimit> {
+    // Getting the time takes much longer than running an instruction
+    const STEPS_BETWEEN_TIME_CHECKS: u64 = 4096;
+
+    match config.max_steps {
+        Some(max_steps) if steps >= max_steps => return Some(ExecutionLimit::Steps(max_steps)),
+        _ => {},
+    }
+    match (config.timeout, deadline) {
+        (Some(timeout), Some(deadline)) if steps % STEPS_BETWEEN_TIME_CHECKS == 0 && Instant::now() >= deadline => {
+            Some(ExecutionLimit::Timeout(timeout))
+        },
+        _ => None,
+    }
+}
+
+/// Reads a byte of input into the cell, following the policy if there is no more input left
+fn read_cell<I: Read, C: Cell>(inp: &mut I, cell: &mut C, eof: EofPolicy, instruction: usize) -> Result<(), RuntimeError> {
+    match read_byte(inp)? {
+        Some(byte) => *cell = C::from_byte(byte),
+        None => match eof {
+            EofPolicy::Zero => *cell = C::default(),
+            EofPolicy::Unchanged => {},
+            EofPolicy::MaxValue => *cell = C::wrap(-1),
+            EofPolicy::Error => return Err(RuntimeError::EndOfInput {instruction}),
+        },
+    }
+    Ok(())
+}
+
+/// Reads a single byte of input, returning None if there is no more input left
+pub(crate) fn read_byte<I: Read + ?Sized>(inp: &mut I) -> Result<Option<u8>, RuntimeError> {
+    let mut inbuffer: [u8; 1] = [0];
+    loop {
+        // Only a read of zero bytes means the end of the input. Any error is a genuine
+        // failure, even one that happens to use the `UnexpectedEof` kind.
+        match inp.read(&mut inbuffer) {
+            Ok(0) => return Ok(None),
+            Ok(_) => return Ok(Some(inbuffer[0])),
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {},
+            Err(err) => return Err(RuntimeError::InputError(err)),
+        }
+    }
+}
+
+/// Returns the index of the first zero cell found by moving right from start by stride cells at
+/// a time. The returned index is past the end of the buffer if no zero cell was found.
+fn find_zero_right<C: Cell>(buffer: &VecDeque<C>, start: usize, stride: usize) -> usize {
+    if stride == 1 {
+        // Searching the contiguous parts of the buffer directly is much faster than indexing
+        let (front, back) = buffer.as_slices();
+        if start < front.len() {
+            if let Some(i) = front[start..].iter().position(|&c| c == C::default()) {
+                return start + i;
+            }
+        }
+        let back_start = start.saturating_sub(front.len());
+        return match back.get(back_start..).and_then(|back| back.iter().position(|&c| c == C::default())) {
+            Some(i) => front.len() + back_start + i,
+            None => start.max(buffer.len()),
+        };
+    }
+
+    let mut index = start;
+    while index < buffer.len() && buffer[index] != C::default() {
+        index += stride;
+    }
+    index
+}
+
+/// Returns the index of the first zero cell found by moving left from start by stride cells at
+/// a time, or None if the start of the buffer was passed without finding a zero cell
+fn find_zero_left<C: Cell>(buffer: &VecDeque<C>, start: usize, stride: usize) -> Option<usize> {
+    if stride == 1 {
+        // Searching the contiguous parts of the buffer directly is much faster than indexing
+        let (front, back) = buffer.as_slices();
+        if start >= front.len() {
+            if let Some(i) = back[..start - front.len() + 1].iter().rposition(|&c| c == C::default()) {
+                return Some(front.len() + i);
+            }
+        }
+        let front_end = if start < front.len() { start + 1 } else { front.len() };
+        return front[..front_end].iter().rposition(|&c| c == C::default());
+    }
+
+    let mut index = start;
+    loop {
+        if buffer[index] == C::default() {
+            return Some(index);
+        }
+        if index < stride {
+            return None;
+        }
+        index -= stride;
+    }
+}
+
+/// The cells of a running program along with its pointer
+struct Tape<C> {
+    buffer: VecDeque<C>,
+    // The index in the buffer of the current cell
+    pointer: usize,
+    config: TapeConfig,
+}
+
+impl<C: Cell> Tape<C> {
+    fn new(config: &TapeConfig) -> Tape<C> {
+        let size = match config.boundary {
+            // Start with a single cell and grow from there
+            TapeBoundary::Grow => 1,
+            _ => config.size.unwrap_or(DEFAULT_TAPE_SIZE).max(1),
+        };
+        Tape {
+            buffer: (0..size).map(|_| C::default()).collect(),
+            pointer: 0,
+            config: config.clone(),
+        }
+    }
+
+    /// Moves the pointer by the given offset, returning None if it cannot be moved there
+    #[inline]
+    fn move_by(&mut self, offset: isize) -> Option<()> {
+        self.pointer = self.cell_at(offset)?;
+        Some(())
+    }
+
+    /// Returns the index in the buffer of the cell at the given offset from the pointer,
+    /// growing the buffer in either direction if that cell does not exist yet. The pointer is
+    /// updated if any cells are added before it.
+    ///
+    /// Returns None if the cell is past the end of the tape and the boundary does not allow
+    /// it.
+    #[inline]
+    fn cell_at(&mut self, offset: isize) -> Option<usize> {
+        let target = self.pointer as isize + offset;
+        if target >= 0 && (target as usize) < self.buffer.len() {
+            return Some(target as usize);
+        }
+        self.cell_past_end(target)
+    }
+
+    /// Same as `cell_at` for a cell whose index relative to the start of the buffer is not in
+    /// the buffer
+    #[cold]
+    fn cell_past_end(&mut self, target: isize) -> Option<usize> {
+        let len = self.buffer.len();
+        match self.config.boundary {
+            TapeBoundary::Grow => {
+                let extra = if target < 0 { target.unsigned_abs() } else { target as usize + 1 - len };
+                if self.config.size.is_some_and(|size| len + extra > size.max(1)) {
+                    return None;
+                }
+                if target < 0 {
+                    for _ in 0..extra {
+                        self.buffer.push_front(C::default());
+                    }
+                    self.pointer += extra;
+                    Some(0)
                 }
                 else {
-                    buffer[pointer] = 0;
+                    self.buffer.resize(len + extra, C::default());
+                    Some(target as usize)
                 }
             },
-            Instruction::JumpForwardIfZero {ref mut matching} => {
-                if buffer[pointer] == 0 {
-                    next_instruction = matching.unwrap_or_else(|| fill_matching(&mut program, next_instruction - 1));
+            TapeBoundary::Wrap => Some(target.rem_euclid(len as isize) as usize),
+            TapeBoundary::Error => None,
+            TapeBoundary::Clamp => Some(if target < 0 { 0 } else { len - 1 }),
+        }
+    }
+
+    /// Moves the pointer by stride cells at a time until it finds a zero cell. Returns false
+    /// if the pointer would keep moving forever without finding one, in which case it is
+    /// left where it is.
+    ///
+    /// Returns None if the pointer moved past the end of the tape and the boundary does not
+    /// allow it.
+    fn scan(&mut self, stride: isize) -> Option<bool> {
+        let len = self.buffer.len();
+        let zero = C::default();
+        // The first position past the end of the tape that the scan reaches if there is no zero
+        // cell before it
+        let (found, beyond) = if stride > 0 {
+            let index = find_zero_right(&self.buffer, self.pointer, stride as usize);
+            (if index < len { Some(index) } else { None }, index as isize)
+        }
+        else {
+            let stride = stride.unsigned_abs();
+            let found = find_zero_left(&self.buffer, self.pointer, stride);
+            (found, (self.pointer % stride) as isize - stride as isize)
+        };
+        if let Some(index) = found {
+            self.pointer = index;
+            return Some(true);
+        }
+
+        match self.config.boundary {
+            // Any new cell that the tape grows to is zero
+            TapeBoundary::Grow | TapeBoundary::Error => {
+                self.move_by(beyond - self.pointer as isize)?;
+                Some(true)
+            },
+            TapeBoundary::Wrap => {
+                // Every cell the scan can reach has been visited after len steps
+                let mut index = self.pointer;
+                for _ in 0..len {
+                    if self.buffer[index] == zero {
+                        self.pointer = index;
+                        return Some(true);
+                    }
+                    index = (index as isize + stride).rem_euclid(len as isize) as usize;
                 }
+                Some(false)
             },
-            Instruction::JumpBackwardUnlessZero {matching} => {
-                if buffer[pointer] != 0 {
-                    next_instruction = matching;
+            TapeBoundary::Clamp => {
+                let end = if stride > 0 { len - 1 } else { 0 };
+                if self.buffer[end] == zero {
+                    self.pointer = end;
+                    Some(true)
+                }
+                else {
+                    Some(false)
                 }
             },
         }
-
-        callback(InterpreterState {
-            current_instruction,
-            instruction: instr,
-            current_pointer: pointer,
-            memory: &buffer,
-        });
     }
 }
 
@@ -95,9 +543,8 @@ pub fn interpret<I, O, F>(mut inp: I, mut out: O, mut program: Vec<Instruction>,
 /// Designed to fill in any JumpForwardIfZero instructions found along the way
 /// so this function doesn't need to be called needlessly.
 ///
-/// # Panics
-/// Panics if a match is not found
-fn fill_matching(mut program: &mut Vec<Instruction>, start: usize) -> usize {
+/// Returns an error if a match is not found
+fn fill_matching(program: &mut [Instruction], start: usize) -> Result<usize, RuntimeError> {
     use super::MAX_NESTED_JUMPS;
     let mut jumps = VecDeque::with_capacity(MAX_NESTED_JUMPS);
 
@@ -105,16 +552,18 @@ fn fill_matching(mut program: &mut Vec<Instruction>, start: usize) -> usize {
     let mut current = start;
     loop {
         if current >= program_size {
-            panic!("Mismatched `[` instruction");
+            return Err(RuntimeError::MismatchedJump {instruction: start});
         }
 
         match program[current] {
             Instruction::JumpForwardIfZero { .. } => jumps.push_back(current),
             Instruction::JumpBackwardUnlessZero { .. } => {
-                let last_forward = jumps.pop_back().expect("Mismatched `]` instruction");
+                // Cannot fail since we always stop as soon as the first `[` is matched
+                let last_forward = jumps.pop_back().expect("bug: unbalanced jump stack");
                 match program[last_forward] {
                     Instruction::JumpForwardIfZero {ref mut matching} => {
-                        debug_assert!(matching.is_none(),
+                        // Nested jumps may have already been filled in when they were run
+                        debug_assert!(last_forward != start || matching.is_none(),
                             "matching was already set which means this function ran needlessly");
                         *matching = Some(current + 1);
                     },
@@ -131,7 +580,7 @@ fn fill_matching(mut program: &mut Vec<Instruction>, start: usize) -> usize {
         current += 1;
     }
 
-    current + 1
+    Ok(current + 1)
 }
 
 #[cfg(test)]
@@ -187,6 +636,172 @@ mod tests {
         ]), vec![0, 255, 1, 1, 2, 1, 0, 255, 0, 1, 255, 1]);
     }
 
+    #[test]
+    fn set() {
+        assert_eq!(test_interpret_output(vec![
+            Increment(3),
+            Set(0),
+            Write,
+            Set(7),
+            Write,
+            Set(-1),
+            Write,
+            Set(256 + 4),
+            Write,
+        ]), vec![0, 7, 255, 4]);
+    }
+
+    #[test]
+    fn multiply() {
+        assert_eq!(test_interpret_output(vec![
+            Increment(3),
+            MulAdd {offset: 1, factor: 2},
+            MulAdd {offset: -2, factor: -1},
+            // Wraps around just like repeatedly adding would
+            MulAdd {offset: 3, factor: 100},
+            Set(0),
+            // Does nothing since the current cell is zero
+            MulAdd {offset: 1, factor: 5},
+            Right(1),
+            Write,
+            Left(3),
+            Write,
+            Right(5),
+            Write,
+        ]), vec![6, 253, 44]);
+    }
+
+    #[test]
+    fn scan() {
+        assert_eq!(test_interpret_output(vec![
+            Increment(1),
+            Right(1),
+            Increment(2),
+            Right(2),
+            Increment(3),
+            Left(3),
+            // Stops at the first zero
+            ScanRight(1),
+            Write,
+            Left(1),
+            Write,
+            // Grows the buffer if there is no zero
+            Right(2),
+            ScanRight(1),
+            Left(1),
+            Write,
+            // Skips over cells
+            Left(3),
+            ScanRight(2),
+            Write,
+            Left(2),
+            // Grows the buffer at the start if there is no zero
+            ScanLeft(1),
+            Write,
+            Right(1),
+            Write,
+            ScanLeft(3),
+            Right(3),
+            Write,
+        ]), vec![0, 2, 3, 0, 0, 1, 1]);
+    }
+
+    #[test]
+    fn scan_past_capacity() {
+        // Make sure scanning works when the buffer is split in two
+        let mut program = vec![Left(1); 10];
+        program.push(Increment(7));
+        program.extend(vec![Right(1), Increment(1)].into_iter().cycle().take(20));
+        program.extend(vec![Left(10), ScanRight(1), Left(1), Write, ScanLeft(1), Right(1), Write]);
+        assert_eq!(test_interpret_output(program), vec![1, 7]);
+    }
+
+    #[test]
+    fn offset_addressing() {
+        assert_eq!(test_interpret_with_input(vec![
+            AddAt {offset: 2, delta: 3},
+            AddAt {offset: -2, delta: -1},
+            ReadAt {offset: 1},
+            WriteAt {offset: 2},
+            WriteAt {offset: -2},
+            WriteAt {offset: 1},
+            Write,
+            // The pointer should still be in the same place after growing the buffer
+            Left(2),
+            Write,
+            Right(4),
+            Write,
+        ], &[42]), vec![3, 255, 42, 0, 255, 3]);
+    }
+
+    #[test]
+    fn wide_cells() {
+        fn run<C: Cell>() -> (Vec<u8>, Vec<C>) {
+            let mut inp: &[u8] = &[200];
+            let mut out = Vec::new();
+            let mut memory = Vec::new();
+            interpret_with_config::<C, _, _, _>(&mut inp, &mut out, vec![
+                // 256 only wraps around to zero in 8-bit cells
+                Increment(256),
+                JumpForwardIfZero {matching: None},
+                AddAt {offset: 1, delta: 65},
+                Set(0),
+                JumpBackwardUnlessZero {matching: 2},
+                Right(1),
+                Write,
+                Right(1),
+                Decrement(1),
+                // Only the lowest byte is written
+                Write,
+                Right(1),
+                Read,
+                MulAdd {offset: 1, factor: 3},
+                Set(70000),
+            ], &InterpreterConfig::default(), |state| {
+                memory = state.memory.iter().cloned().collect();
+                Control::Continue
+            }).unwrap();
+            (out, memory)
+        }
+
+        assert_eq!(run::<u8>(), (vec![0, 255], vec![0, 0, 255, 112, 88]));
+        assert_eq!(run::<u16>(), (vec![65, 255], vec![0, 65, 65535, 4464, 600]));
+        assert_eq!(run::<u32>(), (vec![65, 255], vec![0, 65, 4294967295, 70000, 600]));
+    }
+
+    #[test]
+    fn wide_cells_optimized() {
+        use super::super::{precompile, OptimizationLevel};
+
+        fn run<C: Cell>(source: &[u8], opt: OptimizationLevel) -> Vec<u8> {
+            let mut inp: &[u8] = &[];
+            let mut out = Vec::new();
+            let program = precompile(source.iter(), opt).unwrap();
+            interpret_with_config::<C, _, _, _>(&mut inp, &mut out, program, &InterpreterConfig::default(), |_| Control::Continue).unwrap();
+            out
+        }
+
+        let plus = |n| "+".repeat(n);
+        let sources = [
+            // Only 8-bit cells are zero after 256 increments
+            format!("{}[>+<[-]]>.", plus(256)),
+            format!("{}[->{}<]>.>{}.", plus(300), plus(260), "-".repeat(257)),
+            format!("{}[>{}<+]>.", "-".repeat(512), plus(3)),
+            String::from_utf8(include_bytes!("../examples/hello-world.bf").to_vec()).unwrap(),
+        ];
+        let levels = [OptimizationLevel::Speed, OptimizationLevel::Aggressive, OptimizationLevel::Maximum, OptimizationLevel::Size];
+        for source in &sources {
+            let source = source.as_bytes();
+            for &opt in &levels {
+                assert_eq!(run::<u8>(source, opt), run::<u8>(source, OptimizationLevel::Off));
+                assert_eq!(run::<u16>(source, opt), run::<u16>(source, OptimizationLevel::Off));
+                assert_eq!(run::<u32>(source, opt), run::<u32>(source, OptimizationLevel::Off));
+            }
+        }
+        assert_eq!(run::<u8>(sources[0].as_bytes(), OptimizationLevel::Maximum), vec![0]);
+        assert_eq!(run::<u16>(sources[0].as_bytes(), OptimizationLevel::Maximum), vec![1]);
+    }
+
     #[test]
     fn move_left_past_zero() {
         // These movements are designed to cause problems if the move instructions are not
@@ -284,6 +899,349 @@ mod tests {
         ]), vec![0, 5, 4, 0, 1, 17, 32, 49, 1, 0, 0]);
     }
 
+    #[test]
+    fn eof_policy() {
+        let program = vec![
+            Increment(7),
+            Read,
+            Write,
+            Read,
+            Write,
+            AddAt {offset: 1, delta: 3},
+            ReadAt {offset: 1},
+            WriteAt {offset: 1},
+        ];
+        let run = |eof| {
+            let mut inp: &[u8] = &[42];
+            let mut out = Vec::new();
+            let config = InterpreterConfig {eof, ..InterpreterConfig::default()};
+            interpret_with_config::<u8, _, _, _>(&mut inp, &mut out, program.clone(), &config, |_| Control::Continue)
+                .map(|_| out)
+        };
+
+        assert_eq!(run(EofPolicy::Zero).unwrap(), vec![42, 0, 0]);
+        assert_eq!(run(EofPolicy::Unchanged).unwrap(), vec![42, 42, 3]);
+        assert_eq!(run(EofPolicy::MaxValue).unwrap(), vec![42, 255, 255]);
+        match run(EofPolicy::Error) {
+            Err(RuntimeError::EndOfInput {instruction: 3}) => {},
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        let mut inp: &[u8] = &[];
+        let mut memory = Vec::new();
+        let config = InterpreterConfig {eof: EofPolicy::MaxValue, ..InterpreterConfig::default()};
+        interpret_with_config::<u16, _, _, _>(&mut inp, io::sink(), vec![Read], &config, |state| {
+            memory = state.memory.iter().cloned().collect();
+            Control::Continue
+        }).unwrap();
+        assert_eq!(memory, vec![65535]);
+    }
+
+    #[test]
+    fn tape_boundaries() {
+        let run = |size, boundary, program: Vec<Instruction>| {
+            let mut inp: &[u8] = &[];
+            let mut out = Vec::new();
+            let mut memory = Vec::new();
+            let config = InterpreterConfig {
+                tape: TapeConfig {size: Some(size), boundary},
+                ..InterpreterConfig::default()
+            };
+            interpret_with_config::<u8, _, _, _>(&mut inp, &mut out, program, &config, |state| {
+                memory = state.memory.iter().cloned().collect();
+                Control::Continue
+            }).map(|summary| (out, memory, summary.final_pointer))
+        };
+        let program = vec![Left(1), Increment(2), Right(4), Increment(1), Write, AddAt {offset: -5, delta: 3}];
+
+        assert_eq!(run(3, TapeBoundary::Wrap, program.clone()).unwrap(), (vec![1], vec![1, 3, 2], 0));
+        assert_eq!(run(3, TapeBoundary::Clamp, program.clone()).unwrap(), (vec![1], vec![5, 0, 1], 2));
+        match run(3, TapeBoundary::Error, program.clone()) {
+            Err(RuntimeError::OutOfBounds {instruction: 0}) => {},
+            res => panic!("unexpected result: {:?}", res),
+        }
+        // The tape grows to the left by one cell and then needs five cells to move right
+        assert_eq!(run(5, TapeBoundary::Grow, program[..4].to_vec()).unwrap(), (vec![], vec![2, 0, 0, 0, 1], 4));
+        match run(4, TapeBoundary::Grow, program.clone()) {
+            Err(RuntimeError::OutOfBounds {instruction: 2}) => {},
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        let program = vec![Increment(1), Right(2), Increment(1), Right(1), Increment(1), ScanRight(1)];
+        assert_eq!(run(4, TapeBoundary::Wrap, program.clone()).unwrap().2, 1);
+        assert_eq!(run(4, TapeBoundary::Clamp, vec![Right(2), Increment(1), ScanLeft(4)]).unwrap().2, 0);
+        assert_eq!(run(5, TapeBoundary::Grow, program.clone()).unwrap().2, 4);
+        match run(4, TapeBoundary::Grow, program.clone()) {
+            Err(RuntimeError::OutOfBounds {instruction: 5}) => {},
+            res => panic!("unexpected result: {:?}", res),
+        }
+        match run(4, TapeBoundary::Error, program) {
+            Err(RuntimeError::OutOfBounds {instruction: 5}) => {},
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn endless_scans() {
+        for &boundary in &[TapeBoundary::Wrap, TapeBoundary::Clamp] {
+            let config = TapeConfig {size: Some(4), boundary};
+            let mut tape: Tape<u8> = Tape::new(&config);
+            for cell in tape.buffer.iter_mut() {
+                *cell = 1;
+            }
+            tape.pointer = 1;
+            assert_eq!(tape.scan(2), Some(false));
+            assert_eq!(tape.scan(-1), Some(false));
+            assert_eq!(tape.pointer, 1);
+        }
+    }
+
+    #[test]
+    fn bounded_tapes_optimized() {
+        use super::super::{precompile, OptimizationLevel};
+
+        // Optimizations must not change where a tape wraps around or ends. Moving left first
+        // makes the wrapped tape wrap around as soon as the program starts.
+        let source = include_bytes!("../examples/bsort.bf");
+        let moved: Vec<u8> = b"<<".iter().chain(source.iter()).cloned().collect();
+        // Moving past the end of a clamped tape and back does not return to the same cell
+        let clamped = b">+>++<<>>>>><<.";
+        let levels = [OptimizationLevel::Off, OptimizationLevel::Speed, OptimizationLevel::Maximum];
+        let tapes: [(&[u8], TapeBoundary, usize); 6] = [
+            (&moved, TapeBoundary::Wrap, 100),
+            (&moved, TapeBoundary::Grow, 14),
+            (source, TapeBoundary::Error, 30),
+            (source, TapeBoundary::Error, 12),
+            (source, TapeBoundary::Clamp, 30),
+            (clamped, TapeBoundary::Clamp, 3),
+        ];
+        for &(source, boundary, size) in &tapes {
+            let outputs: Vec<_> = levels.iter().map(|&opt| {
+                let mut out = Vec::new();
+                let config = InterpreterConfig {
+                    tape: TapeConfig {size: Some(size), boundary},
+                    ..InterpreterConfig::default()
+                };
+                let opt = if boundary.allows_optimization() { opt } else { OptimizationLevel::Off };
+                let program = precompile(source.iter(), opt).unwrap();
+                let result = interpret_with_config::<u8, _, _, _>(&b"zyxwvutsrq\n"[..], &mut out, program, &config, |_| Control::Continue);
+                (out, result.is_ok())
+            }).collect();
+            assert!(outputs.iter().all(|output| *output == outputs[0]), "{:?} {}", boundary, size);
+        }
+
+        // Only the movement left after optimizing is checked against the end of the tape
+        let config = InterpreterConfig {
+            tape: TapeConfig {size: Some(3), boundary: TapeBoundary::Error},
+            ..InterpreterConfig::default()
+        };
+        for &opt in &levels {
+            let program = precompile(b"<>+.".iter(), opt).unwrap();
+            let result = interpret_with_config::<u8, _, _, _>(&b""[..], io::sink(), program, &config, |_| Control::Continue);
+            match (opt, result) {
+                (OptimizationLevel::Off, Err(RuntimeError::OutOfBounds {instruction: 0})) => {},
+                (OptimizationLevel::Off, res) => panic!("unexpected result: {:?}", res),
+                (_, res) => assert!(res.is_ok(), "{:?}", res),
+            }
+        }
+    }
+
+    #[test]
+    fn execution_limits() {
+        use std::time::Duration;
+
+        // Loops forever
+        let program = vec![
+            Right(1),
+            Increment(1),
+            JumpForwardIfZero {matching: None},
+            Left(1),
+            Right(1),
+            JumpBackwardUnlessZero {matching: 3},
+        ];
+        let run = |config: InterpreterConfig| {
+            let mut inp: &[u8] = &[];
+            interpret_with_config::<u8, _, _, _>(&mut inp, io::sink(), program.clone(), &config, |_| Control::Continue)
+        };
+
+        match run(InterpreterConfig {max_steps: Some(2), ..InterpreterConfig::default()}) {
+            Err(RuntimeError::LimitExceeded {limit: ExecutionLimit::Steps(2), instruction: 2, pointer: 1, steps: 2}) => {},
+            res => panic!("unexpected result: {:?}", res),
+        }
+        match run(InterpreterConfig {max_steps: Some(1000), ..InterpreterConfig::default()}) {
+            Err(RuntimeError::LimitExceeded {instruction: 4, pointer: 0, steps: 1000, ..}) => {},
+            res => panic!("unexpected result: {:?}", res),
+        }
+        let timeout = Duration::from_millis(10);
+        match run(InterpreterConfig {timeout: Some(timeout), ..InterpreterConfig::default()}) {
+            Err(RuntimeError::LimitExceeded {limit, steps, ..}) => {
+                assert_eq!(limit, ExecutionLimit::Timeout(timeout));
+                assert!(steps > 0);
+            },
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        // Finishing on the last step allowed is not exceeding the limit
+        let mut inp: &[u8] = &[];
+        let config = InterpreterConfig {max_steps: Some(2), ..InterpreterConfig::default()};
+        assert!(interpret_with_config::<u8, _, _, _>(&mut inp, io::sink(), vec![Right(1), Write], &config, |_| Control::Continue).is_ok());
+
+        // A timeout too long to add to the current time never runs out
+        let mut inp: &[u8] = &[];
+        let config = InterpreterConfig {timeout: Some(Duration::from_secs(u64::MAX)), ..InterpreterConfig::default()};
+        assert!(interpret_with_config::<u8, _, _, _>(&mut inp, io::sink(), vec![Right(1), Write], &config, |_| Control::Continue).is_ok());
+    }
+
+    #[test]
+    fn stepping() {
+        let mut inp: &[u8] = &[];
+        let program = vec![
+            Increment(2),
+            JumpForwardIfZero {matching: None},
+            Decrement(1),
+            Right(1),
+            Increment(1),
+            Left(1),
+            JumpBackwardUnlessZero {matching: 2},
+            Write,
+        ];
+        let mut interpreter: Interpreter<_, _> = Interpreter::new(&mut inp, Vec::new(), program);
+        assert_eq!(interpreter.next_instruction(), 0);
+        assert!(!interpreter.is_finished());
+
+        {
+            let state = interpreter.step().unwrap().unwrap();
+            assert_eq!(state.current_instruction, 0);
+            assert_eq!(state.instruction, Increment(2));
+            assert_eq!(state.memory, &VecDeque::from(vec![2]));
+        }
+        assert_eq!(interpreter.steps(), 1);
+        assert_eq!(interpreter.next_instruction(), 1);
+
+        assert_eq!(interpreter.run(4).unwrap(), None);
+        assert_eq!(interpreter.steps(), 5);
+        assert_eq!(interpreter.pointer(), 1);
+        assert_eq!(interpreter.memory(), &VecDeque::from(vec![1, 1]));
+
+        // Stops right after the instruction that made the predicate true
+        assert_eq!(interpreter.run_until(|state| state.memory[0] == 0).unwrap(), None);
+        assert_eq!(interpreter.next_instruction(), 3);
+        assert_eq!(interpreter.steps(), 8);
+
+        let summary = interpreter.run(100).unwrap().unwrap();
+        assert_eq!(summary, ExecutionSummary {steps: 13, final_pointer: 0});
+        assert!(interpreter.is_finished());
+        assert_eq!(interpreter.output(), &vec![0]);
+
+        // Nothing more to run
+        assert!(interpreter.step().unwrap().is_none());
+        assert_eq!(interpreter.run(1).unwrap(), Some(summary));
+        assert_eq!(interpreter.steps(), 13);
+    }
+
+    #[test]
+    fn callback_control() {
+        // Counts up in the first cell forever
+        let program = vec![
+            Increment(1),
+            JumpForwardIfZero {matching: None},
+            Increment(1),
+            Write,
+            JumpBackwardUnlessZero {matching: 2},
+        ];
+
+        // interpret stops for good at either Break or Pause
+        for &control in &[Control::Break, Control::Pause] {
+            let mut inp: &[u8] = &[];
+            let mut out = Vec::new();
+            let summary = interpret_with_config::<u16, _, _, _>(&mut inp, &mut out, program.clone(), &InterpreterConfig::default(), |state| {
+                if state.memory[0] == 5 { control } else { Control::Continue }
+            }).unwrap();
+            assert_eq!(summary, ExecutionSummary {steps: 12, final_pointer: 0});
+            assert_eq!(out, vec![2, 3, 4]);
+        }
+
+        let mut inp: &[u8] = &[];
+        let mut interpreter: Interpreter<_, _, u16> = Interpreter::new(&mut inp, Vec::new(), program);
+        let watch = |state: InterpreterState<u16>| match (state.instruction, state.memory[0]) {
+            (Write, 3) => Control::Pause,
+            (Write, 5) => Control::Break,
+            _ => Control::Continue,
+        };
+
+        // Pausing can be resumed from the next instruction
+        assert_eq!(interpreter.run_with(watch).unwrap(), None);
+        assert_eq!((interpreter.steps(), interpreter.next_instruction()), (7, 4));
+        assert_eq!(interpreter.output(), &vec![2, 3]);
+
+        // Breaking stops the program as if it had finished
+        let summary = interpreter.run_with(watch).unwrap().unwrap();
+        assert_eq!(summary, ExecutionSummary {steps: 13, final_pointer: 0});
+        assert!(interpreter.is_finished());
+        assert!(interpreter.step().unwrap().is_none());
+        assert_eq!(interpreter.run_with(|_| Control::Continue).unwrap(), Some(summary));
+        assert_eq!(interpreter.output(), &vec![2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn resume_same_as_interpret() {
+        use super::super::{precompile, OptimizationLevel};
+
+        let program = precompile(include_bytes!("../examples/hello-world.bf").iter(), OptimizationLevel::Maximum).unwrap();
+
+        let mut inp: &[u8] = &[];
+        let mut expected = Vec::new();
+        let mut states = Vec::new();
+        let expected_summary = interpret(&mut inp, &mut expected, program.clone(), |state| {
+            states.push((state.current_instruction, state.current_pointer));
+            Control::Continue
+        }).unwrap();
+
+        let mut inp: &[u8] = &[];
+        let mut interpreter: Interpreter<_, _> = Interpreter::new(&mut inp, Vec::new(), program);
+        let mut resumed = Vec::new();
+        let summary = loop {
+            let summary = interpreter.run_until(|state| {
+                resumed.push((state.current_instruction, state.current_pointer));
+                resumed.len() % 7 == 0
+            }).unwrap();
+            if let Some(summary) = summary {
+                break summary;
+            }
+        };
+        assert_eq!(summary, expected_summary);
+        assert_eq!(resumed, states);
+        assert_eq!(interpreter.output(), &expected);
+    }
+
+    #[test]
+    fn retry_after_error() {
+        // Input that fails once and then has a byte to read
+        struct FlakyInput(bool);
+        impl io::Read for FlakyInput {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                if !self.0 {
+                    self.0 = true;
+                    return Err(io::Error::new(io::ErrorKind::WouldBlock, "not ready"));
+                }
+                buf[0] = b'a';
+                Ok(1)
+            }
+        }
+
+        let mut interpreter: Interpreter<_, _> = Interpreter::new(FlakyInput(false), io::sink(), vec![Right(1), Read]);
+        match interpreter.run(10) {
+            Err(RuntimeError::InputError(ref err)) if err.kind() == io::ErrorKind::WouldBlock => {},
+            res => panic!("unexpected result: {:?}", res),
+        }
+        assert_eq!(interpreter.next_instruction(), 1);
+        assert_eq!(interpreter.steps(), 1);
+
+        let summary = interpreter.run(10).unwrap().unwrap();
+        assert_eq!(summary, ExecutionSummary {steps: 2, final_pointer: 1});
+        assert_eq!(interpreter.memory(), &VecDeque::from(vec![0, b'a']));
+    }
+
     #[test]
     fn basic_looping() {
         // This loop increments cell index 1 using cell index 0 as a loop counter
@@ -335,21 +1293,103 @@ mod tests {
 
         // hello world program from examples/hello-world.bf
         let source: Vec<u8> = include_bytes!("../examples/hello-world.bf").to_vec();
-        let program = precompile(source.iter(), OptimizationLevel::Off);
+        let program = precompile(source.iter(), OptimizationLevel::Off).unwrap();
+        assert_eq!(test_interpret_output(program),
+            b"Hello World!\n");
+
+        let program = precompile(source.iter(), OptimizationLevel::Speed).unwrap();
         assert_eq!(test_interpret_output(program),
             b"Hello World!\n");
 
-        let program = precompile(source.iter(), OptimizationLevel::Speed);
+        let program = precompile(source.iter(), OptimizationLevel::Maximum).unwrap();
         assert_eq!(test_interpret_output(program),
             b"Hello World!\n");
     }
 
     #[test]
-    #[should_panic(expected = "Mismatched `[` instruction")]
     fn mismatched_jumps() {
-        test_interpret_output(vec![
+        let mut inp: &[u8] = &[];
+        let res = interpret(&mut inp, io::sink(), vec![
+            Increment(1),
+            JumpBackwardUnlessZero {matching: 2},
+            Decrement(1),
+            JumpForwardIfZero {matching: None},
+        ], |_| Control::Continue);
+        match res {
+            Err(RuntimeError::MismatchedJump {instruction: 3}) => {},
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn nested_jumps_filled_out_of_order() {
+        use super::super::{precompile, OptimizationLevel};
+
+        // The innermost jump is filled in before the jump around it is ever skipped
+        let program = precompile(b"++>+<[>[>[-]<-]<-]".iter(), OptimizationLevel::Off).unwrap();
+        let mut inp: &[u8] = &[];
+        assert!(interpret(&mut inp, io::sink(), program, |_| Control::Continue).is_ok());
+    }
+
+    #[test]
+    fn output_error() {
+        struct BrokenPipe;
+        impl io::Write for BrokenPipe {
+            fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+                Err(io::Error::new(io::ErrorKind::BrokenPipe, "broken pipe"))
+            }
+            fn flush(&mut self) -> io::Result<()> { Ok(()) }
+        }
+
+        let mut inp: &[u8] = &[];
+        match interpret(&mut inp, BrokenPipe, vec![Write], |_| Control::Continue) {
+            Err(RuntimeError::OutputError(ref err)) if err.kind() == io::ErrorKind::BrokenPipe => {},
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn input_error() {
+        struct FailingInput;
+        impl io::Read for FailingInput {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+                Err(io::Error::new(io::ErrorKind::PermissionDenied, "permission denied"))
+            }
+        }
+
+        match interpret(FailingInput, io::sink(), vec![Read], |_| Control::Continue) {
+            Err(RuntimeError::InputError(ref err)) if err.kind() == io::ErrorKind::PermissionDenied => {},
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn unexpected_eof_error() {
+        // An error from the input is not the end of the input even if it has the same kind
+        struct TruncatedInput;
+        impl io::Read for TruncatedInput {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+                Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed"))
+            }
+        }
+
+        match interpret(TruncatedInput, io::sink(), vec![Read], |_| Control::Continue) {
+            Err(RuntimeError::InputError(ref err)) if err.kind() == io::ErrorKind::UnexpectedEof => {},
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn execution_summary() {
+        let mut inp: &[u8] = &[];
+        let summary = interpret(&mut inp, io::sink(), vec![
+            Increment(3),
             JumpForwardIfZero {matching: None},
-        ]);
+            Decrement(1),
+            JumpBackwardUnlessZero {matching: 2},
+            Right(2),
+        ], |_| Control::Continue).unwrap();
+        assert_eq!(summary, ExecutionSummary {steps: 9, final_pointer: 2});
     }
 
     #[test]
@@ -364,7 +1404,7 @@ mod tests {
             Decrement(1),
             JumpBackwardUnlessZero {matching: 4},
         ];
-        let states = vec![
+        let states = [
             (0, Right(4), 4, vec![0, 0, 0, 0, 0].into()),
             (1, Left(5), 0, vec![0, 0, 0, 0, 0, 0].into()),
             (2, Increment(2), 0, vec![2, 0, 0, 0, 0, 0].into()),
@@ -381,7 +1421,8 @@ mod tests {
         interpret(&mut inp, &mut out, program, |state| {
             let expected = states.pop_front().expect("callback was called unexpectedly");
             assert_eq!(expected, state, "Failed with {} states left", states.len());
-        });
+            Control::Continue
+        }).unwrap();
 
         assert!(states.is_empty());
         assert_eq!(out, vec![]);
@@ -394,7 +1435,7 @@ mod tests {
 
     fn test_interpret_with_input(program: Vec<Instruction>, mut inp: &[u8]) -> Vec<u8> {
         let mut out = Vec::new();
-        interpret(&mut inp, &mut out, program, |_| {});
+        interpret(&mut inp, &mut out, program, |_| Control::Continue).unwrap();
         out
     }
 }
@@ -0,0 +1,23 @@
+use std::time::Duration;
+
+use super::{EofPolicy, TapeConfig};
+
+/// Options that change how `interpret_with_config` runs a program
+///
+/// The default configuration behaves exactly like `interpret`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct InterpreterConfig {
+    /// What reading does once the end of the input has been reached
+    pub eof: EofPolicy,
+    /// The size of the tape and what happens at its ends
+    pub tape: TapeConfig,
+    /// The most instructions to run before stopping the program with
+    /// `RuntimeError::LimitExceeded`, or None for no limit
+    pub max_steps: Option<u64>,
+    /// How long the program may run before it is stopped with `RuntimeError::LimitExceeded`,
+    /// or None for no limit
+    ///
+    /// The time is only checked every few thousand instructions and cannot interrupt an
+    /// instruction that is waiting for input, so the program may run for a little longer.
+    pub timeout: Option<Duration>,
+}
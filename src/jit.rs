@@ -0,0 +1,698 @@
+use std::io::{Read, Write};
+
+use super::{Instruction, RuntimeError, ExecutionSummary};
+
+/// Runs a program by first compiling it to native machine code
+///
+/// Produces exactly the same output and result as calling `interpret` without a callback, but
+/// runs long programs much faster. On platforms other than x86-64 Unix systems, this simply
+/// calls `interpret`. It also falls back to `interpret` if the system does not allow the
+/// compiled code to be made executable.
+#[cfg(all(target_arch = "x86_64", unix))]
+pub fn interpret_jit<I, O>(mut inp: I, mut out: O, program: Vec<Instruction>) -> Result<ExecutionSummary, RuntimeError>
+    where I: Read, O: Write {
+    x86_64::run(&mut inp, &mut out, &program)
+}
+
+/// Runs a program by first compiling it to native machine code
+///
+/// Produces exactly the same output and result as calling `interpret` without a callback, but
+/// runs long programs much faster. On platforms other than x86-64 Unix systems, this simply
+/// calls `interpret`.
+#[cfg(not(all(target_arch = "x86_64", unix)))]
+pub fn interpret_jit<I, O>(inp: I, out: O, program: Vec<Instruction>) -> Result<ExecutionSummary, RuntimeError>
+    where I: Read, O: Write {
+    super::interpret(inp, out, program, |_| super::Control::Continue)
+}
+
+#[cfg(all(target_arch = "x86_64", unix))]
+mod x86_64 {
+    use std::io::{self, Read, Write};
+    use std::mem;
+    use std::ptr;
+    use std::cmp;
+
+    use libc;
+
+    use super::super::{Instruction, RuntimeError, ExecutionSummary, Control, interpret};
+    use super::super::x86_64::*;
+    use super::super::interpreter::read_byte;
+
+    // The number of cells allocated before the program starts running
+    const TAPE_SIZE: usize = 1 << 16;
+    // The index of the first cell in the initial tape, leaving some room to move left without
+    // having to grow the tape
+    const TAPE_START: usize = TAPE_SIZE / 4;
+
+    // Registers used by the generated code to keep the state of the program. These are all
+    // callee-saved so that calling back into Rust does not clobber them.
+    //
+    // RBX: address of the current cell
+    // R12: address of the Context
+    // R13: address of the leftmost cell (Context::low)
+    // R14: rightmost address the pointer can reach without growing the tape (Context::high)
+    // R15: the number of instructions run so far
+
+    /// Everything the generated code needs to call back into Rust
+    ///
+    /// The generated code reads and writes the first four fields directly, so their layout
+    /// must not change.
+    #[repr(C)]
+    struct Context<'a> {
+        low: *mut u8,
+        high: *mut u8,
+        pointer: *mut u8,
+        steps: u64,
+        tape: Vec<u8>,
+        // index in the tape of the leftmost cell that the program has used
+        start: usize,
+        // the largest positive offset used by any instruction in the program
+        reach: usize,
+        inp: &'a mut dyn Read,
+        out: &'a mut dyn Write,
+        error: Option<RuntimeError>,
+    }
+
+    impl<'a> Context<'a> {
+        fn update_bounds(&mut self) {
+            let base = self.tape.as_mut_ptr();
+            self.low = base.wrapping_add(self.start);
+            self.high = base.wrapping_add(self.tape.len() - 1 - self.reach);
+        }
+    }
+
+    pub fn run(inp: &mut dyn Read, out: &mut dyn Write, program: &[Instruction]) -> Result<ExecutionSummary, RuntimeError> {
+        let reach = program.iter().map(|&instr| match instr {
+            Instruction::MulAdd {offset, ..} |
+            Instruction::AddAt {offset, ..} |
+            Instruction::WriteAt {offset} |
+            Instruction::ReadAt {offset} if offset > 0 => offset as usize,
+            _ => 0,
+        }).max().unwrap_or(0);
+
+        // Executable memory can be denied by the system (e.g. by a policy that forbids
+        // writable memory from becoming executable), so the program still has to run somehow
+        let code = match ExecutableBuffer::new(&compile(program)) {
+            Ok(code) => code,
+            Err(_) => return interpret(inp, out, program.to_vec(), |_| Control::Continue),
+        };
+
+        let mut ctx = Context {
+            low: ptr::null_mut(),
+            high: ptr::null_mut(),
+            pointer: ptr::null_mut(),
+            steps: 0,
+            tape: vec![0; cmp::max(TAPE_SIZE, TAPE_START + reach + 1)],
+            start: TAPE_START,
+            reach,
+            inp,
+            out,
+            error: None,
+        };
+        ctx.update_bounds();
+        let pointer = ctx.low;
+
+        let status = unsafe {
+            let entry: extern "C" fn(*mut Context, *mut u8) -> u32 = mem::transmute(code.ptr);
+            entry(&mut ctx, pointer)
+        };
+
+        if status != 0 {
+            return Err(ctx.error.take().expect("bug: compiled program failed without an error"));
+        }
+
+        let index = ctx.pointer as usize - ctx.tape.as_ptr() as usize;
+        Ok(ExecutionSummary {
+            steps: ctx.steps,
+            final_pointer: index - ctx.start,
+        })
+    }
+
+    /// Writes a byte of output. Returns zero on success.
+    extern "C" fn jit_write(ctx: *mut Context, value: u8) -> u32 {
+        let ctx = unsafe { &mut *ctx };
+        match ctx.out.write_all(&[value]) {
+            Ok(()) => 0,
+            Err(err) => {
+                ctx.error = Some(RuntimeError::OutputError(err));
+                1
+            },
+        }
+    }
+
+    /// Reads a byte of input, returning zero if there is no more input left. Returns a
+    /// negative value on failure.
+    extern "C" fn jit_read(ctx: *mut Context) -> i32 {
+        let ctx = unsafe { &mut *ctx };
+        match read_byte(ctx.inp) {
+            Ok(Some(byte)) => byte as i32,
+            // Running out of input is not an error, it just results in zero
+            Ok(None) => 0,
+            Err(err) => {
+                ctx.error = Some(err);
+                -1
+            },
+        }
+    }
+
+    /// Called when the instruction at the given index jumps to a `]` that does not exist
+    extern "C" fn jit_mismatched_jump(ctx: *mut Context, instruction: usize) {
+        let ctx = unsafe { &mut *ctx };
+        ctx.error = Some(RuntimeError::MismatchedJump {instruction});
+    }
+
+    /// Makes the cell at the given offset from the pointer the leftmost cell, growing the
+    /// tape if necessary. Returns the new address of the pointer.
+    extern "C" fn jit_extend_left(ctx: *mut Context, pointer: *mut u8, offset: isize) -> *mut u8 {
+        let ctx = unsafe { &mut *ctx };
+        let mut index = pointer as isize - ctx.tape.as_ptr() as isize;
+        let mut target = index + offset;
+        if target < 0 {
+            // Grow by at least double to avoid moving the entire tape too often
+            let extra = cmp::max(target.unsigned_abs(), ctx.tape.len());
+            let mut tape = vec![0; extra + ctx.tape.len()];
+            tape[extra..].copy_from_slice(&ctx.tape);
+            ctx.tape = tape;
+            ctx.start += extra;
+            index += extra as isize;
+            target += extra as isize;
+        }
+        ctx.start = cmp::min(ctx.start, target as usize);
+        ctx.update_bounds();
+        ctx.tape.as_mut_ptr().wrapping_offset(index)
+    }
+
+    /// Grows the tape so that the pointer and every cell after it that the program can
+    /// address are in the tape. Returns the new address of the pointer.
+    extern "C" fn jit_extend_right(ctx: *mut Context, pointer: *mut u8) -> *mut u8 {
+        let ctx = unsafe { &mut *ctx };
+        let index = pointer as usize - ctx.tape.as_ptr() as usize;
+        let len = cmp::max(index + ctx.reach + 1, ctx.tape.len() * 2);
+        ctx.tape.resize(len, 0);
+        ctx.update_bounds();
+        ctx.tape.as_mut_ptr().wrapping_add(index)
+    }
+
+    /// A block of memory containing machine code that can be run
+    struct ExecutableBuffer {
+        ptr: *mut libc::c_void,
+        len: usize,
+    }
+
+    impl ExecutableBuffer {
+        fn new(code: &[u8]) -> io::Result<ExecutableBuffer> {
+            let len = code.len();
+            unsafe {
+                let ptr = libc::mmap(ptr::null_mut(), len, libc::PROT_READ | libc::PROT_WRITE,
+                    libc::MAP_PRIVATE | libc::MAP_ANON, -1, 0);
+                if ptr == libc::MAP_FAILED {
+                    return Err(io::Error::last_os_error());
+                }
+                let buffer = ExecutableBuffer {ptr, len};
+
+                ptr::copy_nonoverlapping(code.as_ptr(), ptr as *mut u8, len);
+                if libc::mprotect(ptr, len, libc::PROT_READ | libc::PROT_EXEC) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(buffer)
+            }
+        }
+    }
+
+    impl Drop for ExecutableBuffer {
+        fn drop(&mut self) {
+            unsafe {
+                libc::munmap(self.ptr, self.len);
+            }
+        }
+    }
+
+    /// Compiles the program into a function with the signature
+    /// `extern "C" fn(ctx: *mut Context, pointer: *mut u8) -> u32`
+    /// that returns zero if the program ran successfully
+    fn compile(program: &[Instruction]) -> Vec<u8> {
+        let mut asm = Assembler::new(program.len() + 1);
+        let exit = asm.new_label();
+        let error = asm.new_label();
+        let extend_right = asm.new_label();
+        let extend_left = asm.new_label();
+
+        // Find every `]` for the `[` instructions that have not been matched yet
+        let mut matching: Vec<Option<usize>> = vec![None; program.len()];
+        let mut jumps = Vec::new();
+        for (i, &instr) in program.iter().enumerate() {
+            match instr {
+                Instruction::JumpForwardIfZero {matching: target} => {
+                    matching[i] = target;
+                    jumps.push(i);
+                },
+                Instruction::JumpBackwardUnlessZero {matching: target} => {
+                    matching[i] = Some(target);
+                    if let Some(forward) = jumps.pop() {
+                        if matching[forward].is_none() {
+                            matching[forward] = Some(i + 1);
+                        }
+                    }
+                },
+                _ => {},
+            }
+        }
+        // Every jump lands on an instruction (or the end of the program), which needs a label
+        let mut is_target = vec![false; program.len() + 1];
+        for &target in matching.iter().flat_map(|m| m.iter()) {
+            is_target[cmp::min(target, program.len())] = true;
+        }
+        let target_label = |target: usize| cmp::min(target, program.len());
+
+        // Prologue: save the callee-saved registers (which also aligns the stack to 16 bytes)
+        // and load the state of the program into them
+        asm.push(RBX);
+        asm.push(R12);
+        asm.push(R13);
+        asm.push(R14);
+        asm.push(R15);
+        asm.mov_reg(R12, RDI);
+        asm.mov_reg(RBX, RSI);
+        asm.load_bounds();
+        asm.xor_reg(R15);
+
+        // The steps of straight-line code are counted all at once
+        let mut steps = 0;
+        for (i, &instr) in program.iter().enumerate() {
+            if is_target[i] {
+                asm.add_steps(&mut steps);
+                asm.bind(i);
+            }
+            steps += 1;
+
+            match instr {
+                Instruction::Right(amount) => {
+                    asm.add_rbx(amount as isize);
+                    asm.check_right(extend_right);
+                },
+                Instruction::Left(amount) => {
+                    asm.add_rbx((amount as isize).wrapping_neg());
+                    asm.check_left(0, extend_left);
+                },
+                Instruction::ScanRight(stride) => {
+                    let start = asm.new_label();
+                    let done = asm.new_label();
+                    asm.bind(start);
+                    asm.cmp_cell_zero();
+                    asm.jcc(EQUAL, done);
+                    asm.add_rbx(stride as isize);
+                    asm.cmp_reg(RBX, R14);
+                    asm.jcc(BELOW_OR_EQUAL, start);
+                    asm.call(extend_right);
+                    asm.jmp(start);
+                    asm.bind(done);
+                },
+                Instruction::ScanLeft(stride) => {
+                    let start = asm.new_label();
+                    let done = asm.new_label();
+                    asm.bind(start);
+                    asm.cmp_cell_zero();
+                    asm.jcc(EQUAL, done);
+                    asm.add_rbx((stride as isize).wrapping_neg());
+                    asm.cmp_reg(RBX, R13);
+                    asm.jcc(ABOVE_OR_EQUAL, start);
+                    asm.xor_reg(RDX);
+                    asm.call(extend_left);
+                    asm.jmp(start);
+                    asm.bind(done);
+                },
+                Instruction::Increment(amount) => asm.add_cell(0, amount as u8),
+                Instruction::Decrement(amount) => asm.add_cell(0, (amount as u8).wrapping_neg()),
+                Instruction::Set(value) => asm.set_cell(0, value as u8),
+                Instruction::MulAdd {offset, factor} => {
+                    // The loop this replaced would not have run at all
+                    let skip = asm.new_label();
+                    asm.load_cell(RAX, 0);
+                    asm.test_al();
+                    asm.jcc(EQUAL, skip);
+                    if offset < 0 {
+                        asm.check_left(offset, extend_left);
+                        // The value may have been clobbered by growing the tape
+                        asm.load_cell(RAX, 0);
+                    }
+                    asm.mul_add_cell(offset, factor as u8);
+                    asm.bind(skip);
+                },
+                Instruction::AddAt {offset, delta} => {
+                    asm.check_left(offset, extend_left);
+                    asm.add_cell(offset, delta as u8);
+                },
+                Instruction::Write => asm.write(0, error),
+                Instruction::WriteAt {offset} => {
+                    asm.check_left(offset, extend_left);
+                    asm.write(offset, error);
+                },
+                Instruction::Read => asm.read(0, error),
+                Instruction::ReadAt {offset} => {
+                    asm.check_left(offset, extend_left);
+                    asm.read(offset, error);
+                },
+                Instruction::JumpForwardIfZero { .. } => {
+                    asm.add_steps(&mut steps);
+                    asm.cmp_cell_zero();
+                    match matching[i] {
+                        Some(target) => asm.jcc(EQUAL, target_label(target)),
+                        None => {
+                            let next = asm.new_label();
+                            asm.jcc(NOT_EQUAL, next);
+                            asm.mov_reg(RDI, R12);
+                            asm.mov_imm(RSI, i as u64);
+                            asm.call_fn(jit_mismatched_jump as *const () as usize);
+                            asm.jmp(error);
+                            asm.bind(next);
+                        },
+                    }
+                },
+                Instruction::JumpBackwardUnlessZero {matching: target} => {
+                    asm.add_steps(&mut steps);
+                    asm.cmp_cell_zero();
+                    asm.jcc(NOT_EQUAL, target_label(target));
+                },
+            }
+        }
+        asm.add_steps(&mut steps);
+        asm.bind(program.len());
+
+        // Epilogue: save the final state for Rust to read and restore the registers
+        asm.xor_reg(RAX);
+        asm.bind(exit);
+        asm.store_state();
+        asm.pop(R15);
+        asm.pop(R14);
+        asm.pop(R13);
+        asm.pop(R12);
+        asm.pop(RBX);
+        asm.ret();
+
+        asm.bind(error);
+        asm.mov_imm(RAX, 1);
+        asm.jmp(exit);
+
+        // Subroutines that grow the tape and reload the bounds. extend_left expects the offset
+        // from the pointer of the cell that should become the leftmost cell in RDX.
+        asm.bind(extend_right);
+        asm.call_extend(jit_extend_right as *const () as usize);
+        asm.bind(extend_left);
+        asm.call_extend(jit_extend_left as *const () as usize);
+
+        asm.finish()
+    }
+
+    impl Assembler {
+        fn push(&mut self, reg: u8) {
+            self.rex(false, 0, reg);
+            self.emit(&[0x50 | (reg & 7)]);
+        }
+
+        fn pop(&mut self, reg: u8) {
+            self.rex(false, 0, reg);
+            self.emit(&[0x58 | (reg & 7)]);
+        }
+
+        /// test al, al
+        fn test_al(&mut self) {
+            self.emit(&[0x84, 0xC0]);
+        }
+
+        /// Counts the steps of the code emitted since the last time the steps were counted
+        fn add_steps(&mut self, steps: &mut u32) {
+            if *steps > 0 {
+                // add r15, imm32
+                self.emit(&[0x49, 0x81, 0xC7]);
+                self.emit(&steps.to_le_bytes());
+                *steps = 0;
+            }
+        }
+
+        /// Loads the bounds of the tape from the Context
+        fn load_bounds(&mut self) {
+            // mov r13, [r12]; mov r14, [r12 + 8]
+            self.emit(&[0x4D, 0x8B, 0x6C, 0x24, 0x00]);
+            self.emit(&[0x4D, 0x8B, 0x74, 0x24, 0x08]);
+        }
+
+        /// Stores the pointer and the number of steps into the Context
+        fn store_state(&mut self) {
+            // mov [r12 + 16], rbx; mov [r12 + 24], r15
+            self.emit(&[0x49, 0x89, 0x5C, 0x24, 0x10]);
+            self.emit(&[0x4D, 0x89, 0x7C, 0x24, 0x18]);
+        }
+
+        /// Grows the tape if the pointer moved past the rightmost address it can reach
+        fn check_right(&mut self, extend_right: usize) {
+            let ok = self.new_label();
+            self.cmp_reg(RBX, R14);
+            self.jcc(BELOW_OR_EQUAL, ok);
+            self.call(extend_right);
+            self.bind(ok);
+        }
+
+        /// Makes the cell at the given offset the leftmost cell if it is left of every cell
+        /// that has been used so far
+        fn check_left(&mut self, offset: isize, extend_left: usize) {
+            if offset > 0 {
+                return;
+            }
+            let ok = self.new_label();
+            if offset == 0 {
+                self.cmp_reg(RBX, R13);
+            }
+            else {
+                // Compute the address into RAX without moving the pointer
+                self.mov_imm(RAX, offset as u64);
+                self.rex(true, RBX, RAX);
+                self.emit(&[0x01]);
+                self.modrm_reg(RBX, RAX);
+                self.cmp_reg(RAX, R13);
+            }
+            self.jcc(ABOVE_OR_EQUAL, ok);
+            self.mov_imm(RDX, offset as u64);
+            self.call(extend_left);
+            self.bind(ok);
+        }
+
+        /// Writes the cell as output, jumping to the error label if that fails
+        fn write(&mut self, offset: isize, error: usize) {
+            self.load_cell(RSI, offset);
+            self.mov_reg(RDI, R12);
+            self.call_fn(jit_write as *const () as usize);
+            // test eax, eax
+            self.emit(&[0x85, 0xC0]);
+            self.jcc(NOT_EQUAL, error);
+        }
+
+        /// Reads a byte of input into the cell, jumping to the error label if that fails
+        fn read(&mut self, offset: isize, error: usize) {
+            self.mov_reg(RDI, R12);
+            self.call_fn(jit_read as *const () as usize);
+            // test eax, eax
+            self.emit(&[0x85, 0xC0]);
+            self.jcc(SIGN, error);
+            // The address of the cell is computed after the call since RCX is not preserved
+            let cell = self.cell(offset);
+            // mov byte [cell], al
+            self.emit(&[0x88]);
+            self.modrm_cell(RAX, cell);
+        }
+
+        /// Calls a Rust function. The stack must already be aligned to 16 bytes.
+        fn call_fn(&mut self, address: usize) {
+            self.mov_imm(RAX, address as u64);
+            // call rax
+            self.emit(&[0xFF, 0xD0]);
+        }
+
+        /// Body of a subroutine that grows the tape by calling the given function with the
+        /// Context, the pointer and RDX, then reloads the pointer and the bounds
+        fn call_extend(&mut self, address: usize) {
+            self.mov_reg(RDI, R12);
+            self.mov_reg(RSI, RBX);
+            // Calling this subroutine misaligned the stack by 8 bytes
+            // sub rsp, 8
+            self.emit(&[0x48, 0x83, 0xEC, 0x08]);
+            self.call_fn(address);
+            // add rsp, 8
+            self.emit(&[0x48, 0x83, 0xC4, 0x08]);
+            self.mov_reg(RBX, RAX);
+            self.load_bounds();
+            self.ret();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{interpret, precompile, OptimizationLevel, Control};
+    use super::super::Instruction::*;
+
+    use std::io;
+
+    fn test_same_as_interpreter(program: Vec<Instruction>, input: &[u8]) {
+        let mut expected_output = Vec::new();
+        let expected = interpret(input, &mut expected_output, program.clone(), |_| Control::Continue).unwrap();
+        let mut output = Vec::new();
+        let summary = interpret_jit(input, &mut output, program).unwrap();
+        assert_eq!(output, expected_output);
+        assert_eq!(summary, expected);
+    }
+
+    #[test]
+    fn hello_world() {
+        let source = b"++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.";
+        for &opt in &[OptimizationLevel::Off, OptimizationLevel::Speed, OptimizationLevel::Maximum] {
+            let program = precompile(source.iter(), opt).unwrap();
+            test_same_as_interpreter(program, b"");
+        }
+    }
+
+    #[test]
+    fn examples() {
+        // Some examples never finish (or keep reading after their input ends), so every example
+        // stops once it has written this much output or reads past the end of its input twice
+        const OUTPUT_LIMIT: usize = 256;
+
+        struct LimitedOutput(Vec<u8>);
+        impl io::Write for LimitedOutput {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                let len = buf.len().min(OUTPUT_LIMIT - self.0.len());
+                if len == 0 {
+                    return Err(io::Error::new(io::ErrorKind::BrokenPipe, "output limit reached"));
+                }
+                self.0.extend_from_slice(&buf[..len]);
+                Ok(len)
+            }
+            fn flush(&mut self) -> io::Result<()> { Ok(()) }
+        }
+
+        struct FixedInput<'a>(&'a [u8], bool);
+        impl<'a> io::Read for FixedInput<'a> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                if self.0.is_empty() {
+                    if self.1 {
+                        return Err(io::Error::new(io::ErrorKind::ConnectionReset, "no more input"));
+                    }
+                    self.1 = true;
+                }
+                self.0.read(buf)
+            }
+        }
+
+        let examples: &[(&[u8], &[u8])] = &[
+            (include_bytes!("../examples/99bottles.bf"), b""),
+            (include_bytes!("../examples/LostKingdom.bf"), b"n\nlook\nnorth\nsouth\ninventory\nquit\ny\n"),
+            (include_bytes!("../examples/bsort.bf"), b"hello world sorting test zyx\n"),
+            (include_bytes!("../examples/fib.bf"), b""),
+            (include_bytes!("../examples/hanoi.bf"), b""),
+            (include_bytes!("../examples/hello-world.bf"), b""),
+            (include_bytes!("../examples/mandel.bf"), b""),
+            (include_bytes!("../examples/qsort.bf"), b"hello world sorting test zyx\n"),
+            (include_bytes!("../examples/sierpinski.bf"), b""),
+        ];
+        let levels = [
+            OptimizationLevel::Off,
+            OptimizationLevel::Speed,
+            OptimizationLevel::Aggressive,
+            OptimizationLevel::Maximum,
+            OptimizationLevel::Size,
+        ];
+        for &(source, input) in examples {
+            let mut expected = LimitedOutput(Vec::new());
+            let program = precompile(source.iter(), OptimizationLevel::Off).unwrap();
+            let expected_ok = interpret(FixedInput(input, false), &mut expected, program, |_| Control::Continue).is_ok();
+            assert!(!expected.0.is_empty());
+
+            for &opt in &levels {
+                let mut output = LimitedOutput(Vec::new());
+                let program = precompile(source.iter(), opt).unwrap();
+                let ok = interpret_jit(FixedInput(input, false), &mut output, program).is_ok();
+                assert!(output.0 == expected.0 && ok == expected_ok, "{:?}: {}", opt, String::from_utf8_lossy(&expected.0));
+            }
+        }
+    }
+
+    #[test]
+    fn tape_growth() {
+        // Moves far enough in both directions to grow the tape several times
+        test_same_as_interpreter(vec![
+            Left(100000),
+            Increment(1),
+            Right(300000),
+            Increment(2),
+            AddAt {offset: -500000, delta: 3},
+            Left(500000),
+            ScanLeft(7),
+            Write,
+            Right(500007),
+            ScanRight(1),
+            Increment(5),
+            Write,
+            WriteAt {offset: -500001},
+            MulAdd {offset: -600000, factor: 2},
+            WriteAt {offset: -600000},
+        ], b"");
+    }
+
+    #[test]
+    fn optimized_instructions() {
+        test_same_as_interpreter(vec![
+            Read,
+            MulAdd {offset: 1, factor: 3},
+            MulAdd {offset: -2, factor: -1},
+            MulAdd {offset: 3, factor: 1},
+            Set(0),
+            MulAdd {offset: -5, factor: 2},
+            WriteAt {offset: 1},
+            WriteAt {offset: -2},
+            WriteAt {offset: 3},
+            ReadAt {offset: -3},
+            AddAt {offset: -3, delta: -2},
+            WriteAt {offset: -3},
+            Read,
+            Write,
+        ], b"\x05z");
+    }
+
+    #[test]
+    fn mismatched_jumps() {
+        let mut output = Vec::new();
+        match interpret_jit(&b""[..], &mut output, vec![
+            Write,
+            JumpForwardIfZero {matching: None},
+            Increment(1),
+        ]) {
+            Err(RuntimeError::MismatchedJump {instruction: 1}) => {},
+            result => panic!("unexpected result: {:?}", result),
+        }
+
+        // The jump is never taken, so it does not matter that it is mismatched
+        test_same_as_interpreter(vec![
+            Increment(1),
+            JumpForwardIfZero {matching: None},
+            Write,
+        ], b"");
+    }
+
+    #[test]
+    fn output_error() {
+        struct FailingWriter;
+        impl io::Write for FailingWriter {
+            fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+                Err(io::Error::new(io::ErrorKind::BrokenPipe, "broken pipe"))
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        match interpret_jit(&b""[..], FailingWriter, vec![Increment(1), Write]) {
+            Err(RuntimeError::OutputError(ref err)) if err.kind() == io::ErrorKind::BrokenPipe => {},
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+}
@@ -1,14 +1,55 @@
+#[cfg(feature = "jit")]
+extern crate libc;
+
 mod instruction;
+mod source_position;
+mod source_map;
+mod parse_error;
 mod optlevel;
 mod precompiler;
+mod optimizer;
+mod passes;
+mod cell;
+mod eof_policy;
+mod interpreter_config;
+mod tape_config;
+mod execution_limit;
+mod control;
 mod interpreter;
+#[cfg(feature = "jit")]
+mod jit;
+mod runtime_error;
 mod debug_format;
+mod bytecode;
+mod bytecode_error;
+mod minify;
+mod formatter;
+mod x86_64;
+
+pub mod codegen;
 
 pub use instruction::*;
+pub use source_position::*;
+pub use source_map::*;
+pub use parse_error::*;
 pub use optlevel::*;
+pub use passes::*;
 pub use precompiler::*;
+pub use cell::*;
+pub use eof_policy::*;
+pub use interpreter_config::*;
+pub use tape_config::*;
+pub use execution_limit::*;
+pub use control::*;
 pub use interpreter::*;
+#[cfg(feature = "jit")]
+pub use jit::*;
+pub use runtime_error::*;
 pub use debug_format::*;
+pub use bytecode::*;
+pub use bytecode_error::*;
+pub use minify::*;
+pub use formatter::*;
 
 // We typically don't expect to see more than this many levels of nested jumps
 // based on an analysis of some brainfuck programs
@@ -0,0 +1,222 @@
+use super::{Instruction, ParseError, PassManager, FoldRuns, precompile_with_passes};
+use super::Instruction::*;
+
+/// Renders instructions back into brainfuck source, the inverse of `precompile`
+///
+/// Grouped instructions are expanded (e.g. `+3` becomes `+++`) and instructions produced by
+/// optimizations are rendered as the loops or straight-line code they replace. Precompiling
+/// the result with the same optimizations produces the same instructions again.
+///
+/// Instructions that address a cell by offset move to it and stay there until another cell is
+/// needed, so `add(1, 1) add(2, 2) >3` becomes `>+>++>` rather than `>+<>>++<<>>>`.
+///
+/// # Panics
+/// Panics if a MulAdd instruction is not part of a group of MulAdd instructions followed by
+/// `set(0)`. This cannot happen for a program returned by `precompile`.
+pub fn render_source(program: &[Instruction]) -> String {
+    let mut source = String::new();
+    // How far the pointer of the rendered source is from the pointer of the program
+    let mut position: isize = 0;
+
+    let mut i = 0;
+    while i < program.len() {
+        let instr = program[i];
+        i += 1;
+
+        match instr {
+            // Movement after an offset instruction is combined with the movement back
+            Right(n) if position != 0 => position -= n as isize,
+            Left(n) if position != 0 => position += n as isize,
+            Right(n) => source.extend((0..n).map(|_| '>')),
+            Left(n) => source.extend((0..n).map(|_| '<')),
+            Increment(n) => {
+                move_to(&mut source, &mut position, 0);
+                add(&mut source, n as isize);
+            },
+            Decrement(n) => {
+                move_to(&mut source, &mut position, 0);
+                add(&mut source, -(n as isize));
+            },
+            Write => {
+                move_to(&mut source, &mut position, 0);
+                source.push('.');
+            },
+            Read => {
+                move_to(&mut source, &mut position, 0);
+                source.push(',');
+            },
+            JumpForwardIfZero { .. } => {
+                move_to(&mut source, &mut position, 0);
+                source.push('[');
+            },
+            JumpBackwardUnlessZero { .. } => {
+                move_to(&mut source, &mut position, 0);
+                source.push(']');
+            },
+            Set(value) => {
+                move_to(&mut source, &mut position, 0);
+                source.push_str("[-]");
+                add(&mut source, value);
+            },
+            MulAdd { .. } => {
+                let start = i - 1;
+                while let Some(&MulAdd { .. }) = program.get(i) {
+                    i += 1;
+                }
+                assert!(program.get(i) == Some(&Set(0)),
+                    "A MulAdd instruction must be part of a group followed by set(0)");
+                i += 1;
+
+                move_to(&mut source, &mut position, 0);
+                source.push_str("[-");
+                for &instr in &program[start..i - 1] {
+                    if let MulAdd {offset, factor} = instr {
+                        move_to(&mut source, &mut position, offset);
+                        add(&mut source, factor);
+                    }
+                }
+                move_to(&mut source, &mut position, 0);
+                source.push(']');
+            },
+            ScanRight(n) | ScanLeft(n) => {
+                move_to(&mut source, &mut position, 0);
+                source.push('[');
+                let movement = if let ScanRight(_) = instr { '>' } else { '<' };
+                source.extend((0..n).map(|_| movement));
+                source.push(']');
+            },
+            AddAt {offset, delta} => {
+                move_to(&mut source, &mut position, offset);
+                add(&mut source, delta);
+            },
+            WriteAt {offset} => {
+                move_to(&mut source, &mut position, offset);
+                source.push('.');
+            },
+            ReadAt {offset} => {
+                move_to(&mut source, &mut position, offset);
+                source.push(',');
+            },
+        }
+    }
+
+    // The final movement has no effect, but keeping it lets the source precompile to exactly
+    // the same instructions
+    move_to(&mut source, &mut position, 0);
+    source
+}
+
+/// Strips every comment from the source and cancels out redundant instructions, producing the
+/// shortest equivalent source that this crate can produce
+///
+/// Long runs of increments or decrements are kept as they are rather than replaced with a
+/// shorter run in the other direction, since that only works for cells of one particular
+/// size. The minified program behaves the same as the original with cells of any size.
+pub fn minify<'a, I>(bytes: I) -> Result<String, ParseError>
+    where I: IntoIterator<Item=&'a u8> {
+    let mut passes = PassManager::new();
+    passes.add(FoldRuns);
+    let (program, _) = precompile_with_passes(bytes, &passes)?;
+
+    Ok(render_source(&program))
+}
+
+/// Renders movement from the current position of the rendered pointer to the given offset
+/// from the pointer of the program
+fn move_to(source: &mut String, position: &mut isize, offset: isize) {
+    let movement = if offset > *position { '>' } else { '<' };
+    source.extend((0..(offset - *position).unsigned_abs()).map(|_| movement));
+    *position = offset;
+}
+
+/// Renders increments for a positive amount and decrements for a negative amount
+fn add(source: &mut String, amount: isize) {
+    let arithmetic = if amount > 0 { '+' } else { '-' };
+    source.extend((0..amount.unsigned_abs()).map(|_| arithmetic));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{precompile, interpret, OptimizationLevel, Control};
+
+    #[test]
+    fn render() {
+        assert_eq!(render_source(&[
+            Increment(3),
+            Right(2),
+            JumpForwardIfZero {matching: None},
+            Decrement(1),
+            Left(1),
+            JumpBackwardUnlessZero {matching: 3},
+            Write,
+            Read,
+        ]), "+++>>[-<].,");
+
+        assert_eq!(render_source(&[
+            MulAdd {offset: -1, factor: 2},
+            MulAdd {offset: 2, factor: -1},
+            Set(0),
+            Set(-2),
+            ScanLeft(2),
+            AddAt {offset: 1, delta: 1},
+            WriteAt {offset: 3},
+            ReadAt {offset: -1},
+            Right(1),
+        ]), "[-<++>>>-<<][-]--[<<]>+>>.<<<<,>>");
+    }
+
+    #[test]
+    #[should_panic(expected = "must be part of a group")]
+    fn lone_mul_add() {
+        render_source(&[MulAdd {offset: 1, factor: 1}, Write]);
+    }
+
+    #[test]
+    fn round_trip() {
+        let sources: &[&[u8]] = &[
+            include_bytes!("../examples/hello-world.bf"),
+            include_bytes!("../examples/99bottles.bf"),
+            include_bytes!("../examples/mandel.bf"),
+            include_bytes!("../examples/qsort.bf"),
+            b"+[->+<]>[-]-->>><<<[>>>]>.<-+><",
+        ];
+        let levels = [
+            OptimizationLevel::Off,
+            OptimizationLevel::Speed,
+            OptimizationLevel::Aggressive,
+            OptimizationLevel::Maximum,
+            OptimizationLevel::Size,
+        ];
+        for &source in sources {
+            for &opt in &levels {
+                let program = precompile(source.iter(), opt).unwrap();
+                let rendered = render_source(&program);
+                assert_eq!(precompile(rendered.as_bytes(), opt).unwrap(), program);
+            }
+        }
+    }
+
+    #[test]
+    fn minified() {
+        assert_eq!(minify(b"This + is a + comment ++--- >< >>+<- [.,]".iter()).unwrap(), "+>>+<-[.,]");
+        let cancels: Vec<u8> = (0..200).map(|_| b'+').chain((0..130).map(|_| b'-')).collect();
+        assert_eq!(minify(cancels.iter()).unwrap(), "+".repeat(70));
+        // Only 8-bit cells would wrap around to the same value with fewer instructions
+        let long_run: Vec<u8> = (0..300).map(|_| b'-').collect();
+        assert_eq!(minify(long_run.iter()).unwrap(), "-".repeat(300));
+        assert!(minify(b"[[]".iter()).is_err());
+
+        let source = include_bytes!("../examples/bsort.bf");
+        let minified = minify(source.iter()).unwrap();
+        assert!(minified.len() < source.len());
+        assert_eq!(minify(minified.as_bytes()).unwrap(), minified);
+
+        let run = |source: &[u8]| {
+            let mut output = Vec::new();
+            interpret(&b"minify\n"[..], &mut output, precompile(source.iter(), OptimizationLevel::Off).unwrap(), |_| Control::Continue).unwrap();
+            output
+        };
+        assert_eq!(run(minified.as_bytes()), run(source));
+    }
+}
@@ -0,0 +1,561 @@
+//! Optimizations that run on the instructions of a program before its jumps are filled in
+//!
+//! Every instruction is paired with the span of source that it came from. When several
+//! instructions are replaced with one, the spans are merged so that the source map stays
+//! accurate.
+
+use std::collections::BTreeMap;
+
+use super::{Instruction, Span};
+use super::Instruction::*;
+
+/// Folds runs of adjacent increments and decrements into a single increment or decrement and
+/// runs of adjacent movements into a single movement
+///
+/// Opposing instructions cancel each other out (e.g. `++-` becomes `+` and `><` disappears
+/// entirely). The total is not wrapped around since the size of a cell is only known when the
+/// program runs, so 256 `+` become `+256` rather than disappearing. Comments between
+/// instructions do not stop them from being folded.
+pub fn fold_runs(program: &mut Vec<(Instruction, Span)>) {
+    let mut optimized: Vec<(Instruction, Span)> = Vec::with_capacity(program.len());
+
+    let mut i = 0;
+    while i < program.len() {
+        let (instr, mut span) = program[i];
+        i += 1;
+
+        let folds_with: fn(Instruction) -> bool = if is_movement(instr) {
+            is_movement
+        }
+        else if is_arithmetic(instr) {
+            is_arithmetic
+        }
+        else {
+            optimized.push((instr, span));
+            continue;
+        };
+
+        let mut total = signed_amount(instr);
+        while let Some(&(next, next_span)) = program.get(i) {
+            if !folds_with(next) {
+                break;
+            }
+            total += signed_amount(next);
+            span = span.merge(&next_span);
+            i += 1;
+        }
+
+        let folded = if is_movement(instr) {
+            match total {
+                0 => None,
+                n if n > 0 => Some(Right(n as usize)),
+                n => Some(Left(n.unsigned_abs())),
+            }
+        }
+        else {
+            match total {
+                0 => None,
+                n if n > 0 => Some(Increment(n as usize)),
+                n => Some(Decrement(n.unsigned_abs())),
+            }
+        };
+
+        if let Some(folded) = folded {
+            optimized.push((folded, span));
+        }
+    }
+
+    *program = optimized;
+}
+
+/// The amount that a movement or arithmetic instruction adds to the pointer or the current cell
+fn signed_amount(instr: Instruction) -> isize {
+    match instr {
+        Right(n) | Increment(n) => n as isize,
+        Left(n) | Decrement(n) => -(n as isize),
+        _ => 0,
+    }
+}
+
+/// Replaces loops that clear the current cell (`[-]` and `[+]`) with a single Set instruction
+///
+/// Any increments or decrements directly following the loop are folded into the value that
+/// the cell is set to.
+pub fn clear_loops(program: &mut Vec<(Instruction, Span)>) {
+    let mut optimized = Vec::with_capacity(program.len());
+
+    let mut i = 0;
+    while i < program.len() {
+        let (instr, span) = program[i];
+        i += 1;
+
+        let mut span = match (instr, program.get(i), program.get(i + 1)) {
+            (JumpForwardIfZero { .. }, Some(&(body, _)), Some(&(JumpBackwardUnlessZero { .. }, end)))
+                if body == Increment(1) || body == Decrement(1) => {
+                i += 2;
+                span.merge(&end)
+            },
+            _ => {
+                optimized.push((instr, span));
+                continue;
+            },
+        };
+
+        let mut value: isize = 0;
+        while let Some(&(next, next_span)) = program.get(i) {
+            value = match next {
+                Increment(n) => value.wrapping_add(n as isize),
+                Decrement(n) => value.wrapping_sub(n as isize),
+                _ => break,
+            };
+            span = span.merge(&next_span);
+            i += 1;
+        }
+
+        optimized.push((Set(value), span));
+    }
+
+    *program = optimized;
+}
+
+/// Replaces loops that repeatedly add multiples of the current cell to other cells with
+/// MulAdd instructions
+///
+/// For example, `[->+>++<<]` adds the current cell to the next cell and twice the current cell
+/// to the cell after that. Only loops that contain nothing but movements and arithmetic, that
+/// end where they started and that change the current cell by exactly one each time are
+/// replaced. The loop is replaced with a MulAdd for each cell it changes followed by
+/// clearing the current cell.
+pub fn multiply_loops(program: &mut Vec<(Instruction, Span)>) {
+    let mut optimized = Vec::with_capacity(program.len());
+
+    let mut i = 0;
+    while i < program.len() {
+        let (instr, span) = program[i];
+        i += 1;
+
+        if let JumpForwardIfZero { .. } = instr {
+            if let Some((deltas, len)) = multiply_loop_deltas(&program[i..]) {
+                // Skip the body and the closing "]"
+                i += len;
+                let span = span.merge(&program[i - 1].1);
+
+                let factor = if deltas[&0] == -1 {
+                    1
+                }
+                else {
+                    // Incrementing the current cell until it wraps around to zero runs the loop
+                    // the negation of the current cell times
+                    -1
+                };
+                optimized.extend(deltas.iter()
+                    .filter(|&(&offset, &delta)| offset != 0 && delta != 0)
+                    .map(|(&offset, &delta)| (MulAdd {offset, factor: delta.wrapping_mul(factor)}, span)));
+                optimized.push((Set(0), span));
+                continue;
+            }
+        }
+
+        optimized.push((instr, span));
+    }
+
+    *program = optimized;
+}
+
+/// Replaces loops that only move the pointer (`[>]`, `[<<]`, etc.) with a single instruction
+/// that scans the tape for a zero cell
+pub fn scan_loops(program: &mut Vec<(Instruction, Span)>) {
+    let mut optimized = Vec::with_capacity(program.len());
+
+    let mut i = 0;
+    while i < program.len() {
+        let (instr, span) = program[i];
+        i += 1;
+
+        let scan = match (instr, program.get(i), program.get(i + 1)) {
+            (JumpForwardIfZero { .. }, Some(&(Right(n), _)), Some(&(JumpBackwardUnlessZero { .. }, end))) => {
+                Some((ScanRight(n), end))
+            },
+            (JumpForwardIfZero { .. }, Some(&(Left(n), _)), Some(&(JumpBackwardUnlessZero { .. }, end))) => {
+                Some((ScanLeft(n), end))
+            },
+            _ => None,
+        };
+
+        match scan {
+            Some((scan, end)) => {
+                i += 2;
+                optimized.push((scan, span.merge(&end)));
+            },
+            None => optimized.push((instr, span)),
+        }
+    }
+
+    *program = optimized;
+}
+
+/// Rewrites straight-line code so that the pointer only moves once at the end
+///
+/// Within a run of movements, arithmetic, reads and writes, each instruction is changed to
+/// address its cell by offset from where the pointer was at the start of the run. For example,
+/// `>+>++<<-.` becomes `add(1, 1) add(2, 2) - .` with no movement at all.
+pub fn offset_addressing(program: &mut Vec<(Instruction, Span)>) {
+    let mut optimized = Vec::with_capacity(program.len());
+
+    let mut i = 0;
+    while i < program.len() {
+        let run_len = program[i..].iter()
+            .take_while(|&&(instr, _)| {
+                matches!(instr, Right(..) | Left(..) | Increment(..) | Decrement(..) | Write | Read)
+            })
+            .count();
+        if run_len == 0 {
+            optimized.push(program[i]);
+            i += 1;
+            continue;
+        }
+
+        let run = &program[i..i + run_len];
+        i += run_len;
+
+        let movements = run.iter().filter(|&&(instr, _)| is_movement(instr)).count();
+        // Nothing to gain if the run has no movement or is only movement
+        if movements == 0 || movements == run.len() {
+            optimized.extend_from_slice(run);
+            continue;
+        }
+
+        let mut offset: isize = 0;
+        let mut movement_span: Option<Span> = None;
+        for &(instr, span) in run {
+            let instr = match instr {
+                Right(n) => {
+                    offset += n as isize;
+                    movement_span = Some(movement_span.map_or(span, |movement| movement.merge(&span)));
+                    continue;
+                },
+                Left(n) => {
+                    offset -= n as isize;
+                    movement_span = Some(movement_span.map_or(span, |movement| movement.merge(&span)));
+                    continue;
+                },
+                // No need to use an offset for the cell at the pointer
+                _ if offset == 0 => instr,
+                Increment(n) => AddAt {offset, delta: n as isize},
+                Decrement(n) => AddAt {offset, delta: (n as isize).wrapping_neg()},
+                Write => WriteAt {offset},
+                Read => ReadAt {offset},
+                _ => unreachable!(),
+            };
+            optimized.push((instr, span));
+        }
+
+        let movement_span = movement_span.expect("bug: run should have had movement");
+        if offset > 0 {
+            optimized.push((Right(offset as usize), movement_span));
+        }
+        else if offset < 0 {
+            optimized.push((Left(offset.unsigned_abs()), movement_span));
+        }
+    }
+
+    *program = optimized;
+}
+
+fn is_movement(instr: Instruction) -> bool {
+    matches!(instr, Right(..) | Left(..))
+}
+
+fn is_arithmetic(instr: Instruction) -> bool {
+    matches!(instr, Increment(..) | Decrement(..))
+}
+
+/// Finds the net change made to each cell (by offset from the start of the loop) by a single
+/// iteration of the loop body at the start of the given instructions
+///
+/// Returns None if the loop cannot be replaced with MulAdd instructions. Otherwise, also
+/// returns the number of instructions in the loop body including the "]" at the end.
+fn multiply_loop_deltas(body: &[(Instruction, Span)]) -> Option<(BTreeMap<isize, isize>, usize)> {
+    let mut deltas = BTreeMap::new();
+    let mut offset: isize = 0;
+    for (i, &(instr, _)) in body.iter().enumerate() {
+        match instr {
+            Right(n) => offset += n as isize,
+            Left(n) => offset -= n as isize,
+            Increment(n) => {
+                let delta = deltas.entry(offset).or_insert(0isize);
+                *delta = delta.wrapping_add(n as isize);
+            },
+            Decrement(n) => {
+                let delta = deltas.entry(offset).or_insert(0isize);
+                *delta = delta.wrapping_sub(n as isize);
+            },
+            JumpBackwardUnlessZero { .. } => {
+                let current = deltas.get(&0).cloned().unwrap_or(0);
+                if offset == 0 && (current == 1 || current == -1) {
+                    return Some((deltas, i + 1));
+                }
+                return None;
+            },
+            _ => return None,
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::SourcePosition;
+
+    #[test]
+    fn fold_runs() {
+        test_pass(super::fold_runs, vec![
+            Right(1),
+            Left(1),
+            Right(1),
+            Right(1),
+            Right(1),
+            Increment(1),
+            Increment(1),
+            Decrement(1),
+            JumpForwardIfZero {matching: None},
+            Left(1),
+            Left(1),
+            Write,
+            Increment(1),
+            Decrement(1),
+            Read,
+            Decrement(1),
+            Decrement(1),
+            Increment(1),
+            Right(1),
+            Left(2),
+            JumpBackwardUnlessZero {matching: 0},
+            Increment(200),
+            Increment(56),
+            Increment(3),
+            Decrement(300),
+        ], vec![
+            (Right(3), 0, 5),
+            (Increment(1), 5, 3),
+            (JumpForwardIfZero {matching: None}, 8, 1),
+            (Left(2), 9, 2),
+            (Write, 11, 1),
+            (Read, 14, 1),
+            (Decrement(1), 15, 3),
+            (Left(1), 18, 2),
+            (JumpBackwardUnlessZero {matching: 0}, 20, 1),
+            (Decrement(41), 21, 4),
+        ]);
+    }
+
+    #[test]
+    fn clear_loops() {
+        test_pass(super::clear_loops, vec![
+            Increment(3),
+            JumpForwardIfZero {matching: None},
+            Decrement(1),
+            JumpBackwardUnlessZero {matching: 0},
+            Right(1),
+            JumpForwardIfZero {matching: None},
+            Increment(1),
+            JumpBackwardUnlessZero {matching: 0},
+            Increment(5),
+            Decrement(2),
+            Write,
+            JumpForwardIfZero {matching: None},
+            Decrement(1),
+            JumpBackwardUnlessZero {matching: 0},
+            Decrement(1),
+            // Loops that might never terminate must be left as is
+            JumpForwardIfZero {matching: None},
+            Decrement(2),
+            JumpBackwardUnlessZero {matching: 0},
+            JumpForwardIfZero {matching: None},
+            JumpForwardIfZero {matching: None},
+            Decrement(1),
+            JumpBackwardUnlessZero {matching: 0},
+            JumpBackwardUnlessZero {matching: 0},
+        ], vec![
+            (Increment(3), 0, 1),
+            (Set(0), 1, 3),
+            (Right(1), 4, 1),
+            (Set(3), 5, 5),
+            (Write, 10, 1),
+            (Set(-1), 11, 4),
+            (JumpForwardIfZero {matching: None}, 15, 1),
+            (Decrement(2), 16, 1),
+            (JumpBackwardUnlessZero {matching: 0}, 17, 1),
+            (JumpForwardIfZero {matching: None}, 18, 1),
+            (Set(0), 19, 3),
+            (JumpBackwardUnlessZero {matching: 0}, 22, 1),
+        ]);
+    }
+
+    #[test]
+    fn multiply_loops() {
+        test_pass(super::multiply_loops, vec![
+            Increment(3),
+            // Copy to two cells
+            JumpForwardIfZero {matching: None},
+            Decrement(1),
+            Right(1),
+            Increment(1),
+            Right(1),
+            Increment(2),
+            Left(2),
+            JumpBackwardUnlessZero {matching: 0},
+            // Incrementing instead of decrementing, with cells on both sides
+            JumpForwardIfZero {matching: None},
+            Left(1),
+            Decrement(3),
+            Right(1),
+            Increment(1),
+            Right(2),
+            Increment(1),
+            Left(1),
+            Decrement(1),
+            Increment(1),
+            Left(1),
+            JumpBackwardUnlessZero {matching: 0},
+            // Does not end where it started
+            JumpForwardIfZero {matching: None},
+            Decrement(1),
+            Right(1),
+            JumpBackwardUnlessZero {matching: 0},
+            // Changes the current cell by more than one
+            JumpForwardIfZero {matching: None},
+            Decrement(2),
+            Right(1),
+            Increment(1),
+            Left(1),
+            JumpBackwardUnlessZero {matching: 0},
+            // Contains other instructions
+            JumpForwardIfZero {matching: None},
+            Decrement(1),
+            Right(1),
+            Write,
+            Left(1),
+            JumpBackwardUnlessZero {matching: 0},
+        ], vec![
+            (Increment(3), 0, 1),
+            (MulAdd {offset: 1, factor: 1}, 1, 8),
+            (MulAdd {offset: 2, factor: 2}, 1, 8),
+            (Set(0), 1, 8),
+            (MulAdd {offset: -1, factor: 3}, 9, 12),
+            (MulAdd {offset: 2, factor: -1}, 9, 12),
+            (Set(0), 9, 12),
+            (JumpForwardIfZero {matching: None}, 21, 1),
+            (Decrement(1), 22, 1),
+            (Right(1), 23, 1),
+            (JumpBackwardUnlessZero {matching: 0}, 24, 1),
+            (JumpForwardIfZero {matching: None}, 25, 1),
+            (Decrement(2), 26, 1),
+            (Right(1), 27, 1),
+            (Increment(1), 28, 1),
+            (Left(1), 29, 1),
+            (JumpBackwardUnlessZero {matching: 0}, 30, 1),
+            (JumpForwardIfZero {matching: None}, 31, 1),
+            (Decrement(1), 32, 1),
+            (Right(1), 33, 1),
+            (Write, 34, 1),
+            (Left(1), 35, 1),
+            (JumpBackwardUnlessZero {matching: 0}, 36, 1),
+        ]);
+    }
+
+    #[test]
+    fn scan_loops() {
+        test_pass(super::scan_loops, vec![
+            JumpForwardIfZero {matching: None},
+            Right(1),
+            JumpBackwardUnlessZero {matching: 0},
+            JumpForwardIfZero {matching: None},
+            JumpForwardIfZero {matching: None},
+            Left(3),
+            JumpBackwardUnlessZero {matching: 0},
+            Decrement(1),
+            JumpBackwardUnlessZero {matching: 0},
+            JumpForwardIfZero {matching: None},
+            Right(1),
+            Left(1),
+            JumpBackwardUnlessZero {matching: 0},
+        ], vec![
+            (ScanRight(1), 0, 3),
+            (JumpForwardIfZero {matching: None}, 3, 1),
+            (ScanLeft(3), 4, 3),
+            (Decrement(1), 7, 1),
+            (JumpBackwardUnlessZero {matching: 0}, 8, 1),
+            (JumpForwardIfZero {matching: None}, 9, 1),
+            (Right(1), 10, 1),
+            (Left(1), 11, 1),
+            (JumpBackwardUnlessZero {matching: 0}, 12, 1),
+        ]);
+    }
+
+    #[test]
+    fn offset_addressing() {
+        test_pass(super::offset_addressing, vec![
+            Right(1),
+            Increment(1),
+            Right(1),
+            Increment(2),
+            Left(2),
+            Decrement(1),
+            Write,
+            Left(1),
+            Read,
+            Decrement(3),
+            Write,
+            Right(2),
+            JumpForwardIfZero {matching: None},
+            // Only movement
+            Right(1),
+            Left(3),
+            JumpForwardIfZero {matching: None},
+            // No movement
+            Increment(1),
+            Write,
+            JumpBackwardUnlessZero {matching: 0},
+            // Ends where it started
+            Right(1),
+            Write,
+            Left(1),
+            JumpBackwardUnlessZero {matching: 0},
+        ], vec![
+            (AddAt {offset: 1, delta: 1}, 1, 1),
+            (AddAt {offset: 2, delta: 2}, 3, 1),
+            (Decrement(1), 5, 1),
+            (Write, 6, 1),
+            (ReadAt {offset: -1}, 8, 1),
+            (AddAt {offset: -1, delta: -3}, 9, 1),
+            (WriteAt {offset: -1}, 10, 1),
+            (Right(1), 0, 12),
+            (JumpForwardIfZero {matching: None}, 12, 1),
+            (Right(1), 13, 1),
+            (Left(3), 14, 1),
+            (JumpForwardIfZero {matching: None}, 15, 1),
+            (Increment(1), 16, 1),
+            (Write, 17, 1),
+            (JumpBackwardUnlessZero {matching: 0}, 18, 1),
+            (WriteAt {offset: 1}, 20, 1),
+            (JumpBackwardUnlessZero {matching: 0}, 22, 1),
+        ]);
+    }
+
+    /// Runs the pass on a program where each instruction comes from a single byte of source
+    /// and checks the resulting instructions as well as the offset and length of their spans
+    fn test_pass<F>(pass: F, program: Vec<Instruction>, expected: Vec<(Instruction, usize, usize)>)
+        where F: FnOnce(&mut Vec<(Instruction, Span)>) {
+        let mut program = program.into_iter().enumerate().map(|(offset, instr)| {
+            (instr, Span {start: SourcePosition {offset, line: 1, column: offset + 1}, len: 1})
+        }).collect();
+        pass(&mut program);
+
+        let actual: Vec<_> = program.into_iter()
+            .map(|(instr, span)| (instr, span.start.offset, span.len))
+            .collect();
+        assert_eq!(actual, expected);
+    }
+}
@@ -4,8 +4,28 @@ use std::str::FromStr;
 pub enum OptimizationLevel {
     // Do not optimize
     Off,
-    // Optimize for speed
+    // Optimize for speed using only the simplest optimizations
     Speed,
+    // Optimize for speed by also replacing common kinds of loops
+    Aggressive,
+    // Optimize for speed using every available optimization
+    Maximum,
+    // Optimize for size using only the optimizations that shrink the program without
+    // replacing a single loop with several instructions or rewriting straight-line code
+    Size,
+}
+
+impl OptimizationLevel {
+    /// The names of the built-in passes run at this optimization level, in order
+    pub fn passes(&self) -> &'static [&'static str] {
+        match *self {
+            OptimizationLevel::Off => &[],
+            OptimizationLevel::Speed => &["fold", "clear"],
+            OptimizationLevel::Aggressive => &["fold", "clear", "mul", "scan"],
+            OptimizationLevel::Maximum => &["fold", "clear", "mul", "scan", "offset"],
+            OptimizationLevel::Size => &["fold", "clear", "scan"],
+        }
+    }
 }
 
 impl FromStr for OptimizationLevel {
@@ -15,6 +35,9 @@ impl FromStr for OptimizationLevel {
         match val {
             "0" => Ok(OptimizationLevel::Off),
             "1" => Ok(OptimizationLevel::Speed),
+            "2" => Ok(OptimizationLevel::Aggressive),
+            "3" => Ok(OptimizationLevel::Maximum),
+            "s" => Ok(OptimizationLevel::Size),
             _ => Err(()),
         }
     }
@@ -30,7 +53,22 @@ mod tests {
         assert_eq!(opt, OptimizationLevel::Off);
         let opt: OptimizationLevel = "1".parse().unwrap();
         assert_eq!(opt, OptimizationLevel::Speed);
+        let opt: OptimizationLevel = "2".parse().unwrap();
+        assert_eq!(opt, OptimizationLevel::Aggressive);
+        let opt: OptimizationLevel = "3".parse().unwrap();
+        assert_eq!(opt, OptimizationLevel::Maximum);
+        let opt: OptimizationLevel = "s".parse().unwrap();
+        assert_eq!(opt, OptimizationLevel::Size);
 
-        assert!("2".parse::<OptimizationLevel>().is_err());
+        assert!("4".parse::<OptimizationLevel>().is_err());
+        assert!("fast".parse::<OptimizationLevel>().is_err());
+    }
+
+    #[test]
+    fn passes() {
+        assert!(OptimizationLevel::Off.passes().is_empty());
+        // Each level should build on the one before it
+        assert!(OptimizationLevel::Aggressive.passes().starts_with(OptimizationLevel::Speed.passes()));
+        assert!(OptimizationLevel::Maximum.passes().starts_with(OptimizationLevel::Aggressive.passes()));
     }
 }
@@ -0,0 +1,87 @@
+use std::fmt;
+use std::error::Error;
+
+use super::SourcePosition;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ParseErrorKind {
+    /// A "[" instruction with no matching "]"
+    UnmatchedJumpForward,
+    /// A "]" instruction with no matching "["
+    UnmatchedJumpBackward,
+}
+
+/// A single jump instruction that could not be matched with another jump instruction
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct UnmatchedJump {
+    pub kind: ParseErrorKind,
+    /// Where the offending instruction was found in the source
+    pub position: SourcePosition,
+}
+
+impl fmt::Display for UnmatchedJump {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let instr = match self.kind {
+            ParseErrorKind::UnmatchedJumpForward => "[",
+            ParseErrorKind::UnmatchedJumpBackward => "]",
+        };
+        write!(f, "unmatched `{}` at {}", instr, self.position)
+    }
+}
+
+/// Returned when a program could not be precompiled
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ParseError {
+    /// Every unmatched jump instruction in the program, ordered by where they appear in the
+    /// source. This is never empty.
+    pub unmatched: Vec<UnmatchedJump>,
+}
+
+impl ParseError {
+    /// The kind of the first error in the program
+    pub fn kind(&self) -> ParseErrorKind {
+        self.unmatched[0].kind
+    }
+
+    /// The position of the first error in the program
+    pub fn position(&self) -> SourcePosition {
+        self.unmatched[0].position
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Mismatched jump instruction: {}", self.unmatched[0])?;
+        if self.unmatched.len() > 1 {
+            write!(f, " (and {} more)", self.unmatched.len() - 1)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for ParseError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display() {
+        let forward = UnmatchedJump {
+            kind: ParseErrorKind::UnmatchedJumpForward,
+            position: SourcePosition {offset: 0, line: 1, column: 1},
+        };
+        let backward = UnmatchedJump {
+            kind: ParseErrorKind::UnmatchedJumpBackward,
+            position: SourcePosition {offset: 7, line: 2, column: 3},
+        };
+        assert_eq!(forward.to_string(), "unmatched `[` at line 1, column 1");
+        assert_eq!(backward.to_string(), "unmatched `]` at line 2, column 3");
+
+        let err = ParseError {unmatched: vec![backward]};
+        assert_eq!(err.to_string(), "Mismatched jump instruction: unmatched `]` at line 2, column 3");
+        let err = ParseError {unmatched: vec![forward, backward]};
+        assert_eq!(err.to_string(),
+            "Mismatched jump instruction: unmatched `[` at line 1, column 1 (and 1 more)");
+    }
+}
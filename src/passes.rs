@@ -0,0 +1,222 @@
+use super::{Instruction, Span, OptimizationLevel};
+use super::optimizer;
+
+/// An optimization that transforms the instructions of a program during precompilation
+///
+/// Each instruction is paired with the span of source that it came from. When replacing
+/// several instructions with one, merge their spans (see `Span::merge`) so that the source map
+/// stays accurate.
+///
+/// Passes run before the targets of jump instructions are filled in, so the `matching` field
+/// of every jump should be ignored. Passes must keep the jump instructions in the program
+/// balanced, otherwise precompiling fails with a `ParseError`.
+pub trait Pass {
+    /// The name used to select this pass (e.g. with the `--passes` command line option)
+    fn name(&self) -> &str;
+
+    /// Optimize the given program in place
+    fn run(&self, program: &mut Vec<(Instruction, Span)>);
+}
+
+/// Folds adjacent arithmetic and movement, cancelling out opposing instructions
+pub struct FoldRuns;
+
+impl Pass for FoldRuns {
+    fn name(&self) -> &str {
+        "fold"
+    }
+
+    fn run(&self, program: &mut Vec<(Instruction, Span)>) {
+        optimizer::fold_runs(program);
+    }
+}
+
+/// Replaces `[-]` and `[+]` with Set instructions
+pub struct ClearLoops;
+
+impl Pass for ClearLoops {
+    fn name(&self) -> &str {
+        "clear"
+    }
+
+    fn run(&self, program: &mut Vec<(Instruction, Span)>) {
+        optimizer::clear_loops(program);
+    }
+}
+
+/// Replaces loops like `[->+<]` with MulAdd instructions
+pub struct MultiplyLoops;
+
+impl Pass for MultiplyLoops {
+    fn name(&self) -> &str {
+        "mul"
+    }
+
+    fn run(&self, program: &mut Vec<(Instruction, Span)>) {
+        optimizer::multiply_loops(program);
+    }
+}
+
+/// Replaces loops like `[>]` with ScanRight and ScanLeft instructions
+pub struct ScanLoops;
+
+impl Pass for ScanLoops {
+    fn name(&self) -> &str {
+        "scan"
+    }
+
+    fn run(&self, program: &mut Vec<(Instruction, Span)>) {
+        optimizer::scan_loops(program);
+    }
+}
+
+/// Rewrites straight-line code to address cells by offset and only move the pointer once
+pub struct OffsetAddressing;
+
+impl Pass for OffsetAddressing {
+    fn name(&self) -> &str {
+        "offset"
+    }
+
+    fn run(&self, program: &mut Vec<(Instruction, Span)>) {
+        optimizer::offset_addressing(program);
+    }
+}
+
+/// Returns the built-in pass with the given name, if there is one
+pub fn builtin_pass(name: &str) -> Option<Box<dyn Pass>> {
+    Some(match name {
+        "fold" => Box::new(FoldRuns),
+        "clear" => Box::new(ClearLoops),
+        "mul" => Box::new(MultiplyLoops),
+        "scan" => Box::new(ScanLoops),
+        "offset" => Box::new(OffsetAddressing),
+        _ => return None,
+    })
+}
+
+/// An ordered list of passes to run on a program
+#[derive(Default)]
+pub struct PassManager {
+    passes: Vec<Box<dyn Pass>>,
+}
+
+impl PassManager {
+    /// Creates a pass manager with no passes
+    pub fn new() -> PassManager {
+        Default::default()
+    }
+
+    /// Creates a pass manager with the built-in passes for the given optimization level
+    pub fn for_level(opt: OptimizationLevel) -> PassManager {
+        let mut manager = PassManager::new();
+        for name in opt.passes() {
+            manager.add_boxed(builtin_pass(name).expect("bug: unknown pass in optimization level"));
+        }
+        manager
+    }
+
+    /// Adds a pass to run after all of the passes that were added before it
+    pub fn add<P: Pass + 'static>(&mut self, pass: P) {
+        self.add_boxed(Box::new(pass));
+    }
+
+    /// Same as `add`, but for a pass that is already boxed, such as one returned by
+    /// `builtin_pass` or one whose type is only known at runtime
+    pub fn add_boxed(&mut self, pass: Box<dyn Pass>) {
+        self.passes.push(pass);
+    }
+
+    /// The names of the passes that will run, in order
+    pub fn names(&self) -> Vec<&str> {
+        self.passes.iter().map(|pass| pass.name()).collect()
+    }
+
+    /// Runs every pass on the program in order
+    pub fn run(&self, program: &mut Vec<(Instruction, Span)>) {
+        for pass in &self.passes {
+            pass.run(program);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{precompile_with_passes, SourcePosition, ParseErrorKind};
+    use super::super::Instruction::*;
+
+    #[test]
+    fn builtin_passes() {
+        for &name in &["fold", "clear", "mul", "scan", "offset"] {
+            assert_eq!(builtin_pass(name).unwrap().name(), name);
+        }
+        assert!(builtin_pass("foo").is_none());
+    }
+
+    #[test]
+    fn for_level() {
+        assert!(PassManager::for_level(OptimizationLevel::Off).names().is_empty());
+        assert_eq!(PassManager::for_level(OptimizationLevel::Maximum).names(),
+            vec!["fold", "clear", "mul", "scan", "offset"]);
+    }
+
+    #[test]
+    fn custom_pass() {
+        // Replaces every write with two writes
+        struct DoubleWrites;
+        impl Pass for DoubleWrites {
+            fn name(&self) -> &str {
+                "double-writes"
+            }
+
+            fn run(&self, program: &mut Vec<(Instruction, Span)>) {
+                *program = program.iter().flat_map(|&(instr, span)| match instr {
+                    Write => vec![(Write, span), (Write, span)],
+                    _ => vec![(instr, span)],
+                }).collect();
+            }
+        }
+
+        let mut passes = PassManager::new();
+        passes.add(DoubleWrites);
+        passes.add(FoldRuns);
+        assert_eq!(passes.names(), vec!["double-writes", "fold"]);
+
+        let (instructions, map) = precompile_with_passes(b"+.[>>.]".iter(), &passes).unwrap();
+        assert_eq!(instructions, vec![
+            Increment(1),
+            Write,
+            Write,
+            JumpForwardIfZero {matching: None},
+            Right(2),
+            Write,
+            Write,
+            JumpBackwardUnlessZero {matching: 4},
+        ]);
+        assert_eq!(map.get(2).unwrap().start, SourcePosition {offset: 1, line: 1, column: 2});
+        assert_eq!(map.get(4).unwrap().len, 2);
+    }
+
+    #[test]
+    fn unbalanced_pass() {
+        // Removes every "]"
+        struct DropJumps;
+        impl Pass for DropJumps {
+            fn name(&self) -> &str {
+                "drop-jumps"
+            }
+
+            fn run(&self, program: &mut Vec<(Instruction, Span)>) {
+                program.retain(|&(instr, _)| !matches!(instr, JumpBackwardUnlessZero { .. }));
+            }
+        }
+
+        let mut passes = PassManager::new();
+        passes.add(DropJumps);
+        let err = precompile_with_passes(b"+\n[[-]>]".iter(), &passes).unwrap_err();
+        assert_eq!(err.unmatched.len(), 2);
+        assert_eq!(err.kind(), ParseErrorKind::UnmatchedJumpForward);
+        assert_eq!(err.position(), SourcePosition {offset: 2, line: 2, column: 1});
+    }
+}
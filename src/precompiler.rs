@@ -1,71 +1,135 @@
 use std::collections::VecDeque;
-use std::iter::repeat;
 
-use super::{OptimizationLevel, Instruction, MAX_NESTED_JUMPS};
+use super::{
+    OptimizationLevel,
+    PassManager,
+    Instruction,
+    ParseError,
+    ParseErrorKind,
+    UnmatchedJump,
+    SourcePosition,
+    SourceMap,
+    Span,
+    MAX_NESTED_JUMPS,
+};
 
 /// Precompile the program into an appropriate in-memory representation
-pub fn precompile<'a, I>(bytes: I, opt: OptimizationLevel) -> Vec<Instruction>
+///
+/// Returns an error listing every unmatched jump instruction if the jumps in the program are
+/// not balanced
+pub fn precompile<'a, I>(bytes: I, opt: OptimizationLevel) -> Result<Vec<Instruction>, ParseError>
+    where I: IntoIterator<Item=&'a u8> {
+    precompile_with_source_map(bytes, opt).map(|(instructions, _)| instructions)
+}
+
+/// Precompile the program and also produce a map from each instruction back to the source
+/// that it came from
+pub fn precompile_with_source_map<'a, I>(bytes: I, opt: OptimizationLevel) -> Result<(Vec<Instruction>, SourceMap), ParseError>
+    where I: IntoIterator<Item=&'a u8> {
+    precompile_with_passes(bytes, &PassManager::for_level(opt))
+}
+
+/// Precompile the program using the given optimization passes instead of the passes from
+/// an optimization level
+pub fn precompile_with_passes<'a, I>(bytes: I, passes: &PassManager) -> Result<(Vec<Instruction>, SourceMap), ParseError>
     where I: IntoIterator<Item=&'a u8> {
     use self::Instruction::*;
 
-    let should_group = opt == OptimizationLevel::Speed;
-
-    let mut input = bytes.into_iter().peekable();
-    let mut instructions = VecDeque::new();
-    while let Some(next_ch) = input.next() {
-        let mut count = 1;
-        if should_group {
-            while let Some(&ch) = input.peek() {
-                if ch == next_ch {
-                    count += 1;
-                    input.next();
-                }
-                else {
-                    break;
-                }
-            }
+    // Each instruction is kept alongside the span of source it came from so that
+    // optimizations can keep the source map accurate
+    let mut program: Vec<(Instruction, Span)> = Vec::new();
+
+    // Jumps are only checked here, their targets are filled in after optimization since
+    // optimizations may change the index of each instruction
+    let mut jump_stack = VecDeque::with_capacity(MAX_NESTED_JUMPS);
+    let mut unmatched = Vec::new();
+
+    let mut line = 1;
+    let mut line_start = 0;
+    for (offset, &ch) in bytes.into_iter().enumerate() {
+        let position = SourcePosition {offset, line, column: offset - line_start + 1};
+        if ch == b'\n' {
+            line += 1;
+            line_start = offset + 1;
         }
 
-        match *next_ch {
-            b'>' => instructions.push_back(Instruction::Right(count)),
-            b'<' => instructions.push_back(Instruction::Left(count)),
-            b'+' => instructions.push_back(Instruction::Increment(count)),
-            b'-' => instructions.push_back(Instruction::Decrement(count)),
-            b'.' => instructions.extend(repeat(Instruction::Write).take(count)),
-            b',' => instructions.extend(repeat(Instruction::Read).take(count)),
-            b'[' => instructions.extend(repeat(Instruction::JumpForwardIfZero {matching: None}).take(count)),
-            b']' => instructions.extend(repeat(Instruction::JumpBackwardUnlessZero {matching: 0}).take(count)),
+        let instr = match ch {
+            b'>' => Right(1),
+            b'<' => Left(1),
+            b'+' => Increment(1),
+            b'-' => Decrement(1),
+            b'.' => Write,
+            b',' => Read,
+            b'[' => {
+                jump_stack.push_back(position);
+                JumpForwardIfZero {matching: None}
+            },
+            b']' => {
+                if jump_stack.pop_back().is_none() {
+                    unmatched.push(UnmatchedJump {
+                        kind: ParseErrorKind::UnmatchedJumpBackward,
+                        position,
+                    });
+                }
+                JumpBackwardUnlessZero {matching: 0}
+            },
             _ => continue,
         };
+
+        program.push((instr, Span {start: position, len: 1}));
     }
 
-    // Used to keep track of jump instructions so we can cache where they jump to so that
-    // jumping is faster when this code runs
-    let mut jump_stack = VecDeque::with_capacity(MAX_NESTED_JUMPS);
+    unmatched.extend(jump_stack.into_iter().map(|position| UnmatchedJump {
+        kind: ParseErrorKind::UnmatchedJumpForward,
+        position,
+    }));
+    if !unmatched.is_empty() {
+        unmatched.sort_by_key(|jump| jump.position.offset);
+        return Err(ParseError {unmatched});
+    }
 
-    // We have to do this in two separate steps or else the indexes would be all wrong
-    let instructions = instructions.into_iter().enumerate().map(|(i, c)|
-        match c {
-            JumpForwardIfZero { .. } => {
-                jump_stack.push_back(i);
-                c
-            },
-            JumpBackwardUnlessZero { .. } => {
-                let matching = jump_stack.pop_back()
-                    .expect("Mismatched jump instruction");
+    passes.run(&mut program);
+
+    let (mut instructions, spans): (Vec<_>, Vec<_>) = program.into_iter().unzip();
+    fill_jumps(&mut instructions, &spans)?;
+
+    Ok((instructions, SourceMap::new(spans)))
+}
+
+/// Fills in the matching instruction of every "]" so that jumping is faster when this code
+/// runs.
+///
+/// Returns an error if the jumps in the program are not balanced. This can only happen if one
+/// of the optimization passes did not keep them balanced, so the error points to the source
+/// that each unmatched instruction came from.
+fn fill_jumps(instructions: &mut [Instruction], spans: &[Span]) -> Result<(), ParseError> {
+    let mut jump_stack = VecDeque::with_capacity(MAX_NESTED_JUMPS);
+    let mut unmatched = Vec::new();
+    for (i, instr) in instructions.iter_mut().enumerate() {
+        match *instr {
+            Instruction::JumpForwardIfZero { .. } => jump_stack.push_back(i),
+            Instruction::JumpBackwardUnlessZero {ref mut matching} => match jump_stack.pop_back() {
                 // When jumping backward, jump one further than the matching [ instruction
                 // This avoids an extra jump test
-                JumpBackwardUnlessZero {matching: matching + 1}
-            }
-            _ => c,
+                Some(start) => *matching = start + 1,
+                None => unmatched.push(UnmatchedJump {
+                    kind: ParseErrorKind::UnmatchedJumpBackward,
+                    position: spans[i].start,
+                }),
+            },
+            _ => {},
         }
-    ).collect();
-
-    if !jump_stack.is_empty() {
-        panic!("Mismatched jump instruction");
     }
 
-    instructions
+    unmatched.extend(jump_stack.into_iter().map(|i| UnmatchedJump {
+        kind: ParseErrorKind::UnmatchedJumpForward,
+        position: spans[i].start,
+    }));
+    if !unmatched.is_empty() {
+        unmatched.sort_by_key(|jump| jump.position.offset);
+        return Err(ParseError {unmatched});
+    }
+    Ok(())
 }
 
 #[cfg(test)]
@@ -77,6 +141,7 @@ mod tests {
     #[test]
     fn optimization_levels() {
         // A program with no groups of adjacent instructions
+        #[allow(clippy::redundant_static_lifetimes)]
         const NO_GROUPS: &'static [u8] = b"><[.][.][,],+-.>-,+";
 
         let no_groups_instructions = vec![
@@ -101,15 +166,32 @@ mod tests {
             Increment(1),
         ];
 
-        // If there are no groups in the instructions, it should be the same with or without
-        // optimizations
-        test_precompile(NO_GROUPS, OptimizationLevel::Off, no_groups_instructions.clone());
-        test_precompile(NO_GROUPS, OptimizationLevel::Speed, no_groups_instructions.clone());
+        // Even without groups, opposing instructions next to each other cancel out when
+        // optimizing
+        test_precompile(NO_GROUPS, OptimizationLevel::Off, no_groups_instructions);
+        test_precompile(NO_GROUPS, OptimizationLevel::Speed, vec![
+            JumpForwardIfZero {matching: None},
+            Write,
+            JumpBackwardUnlessZero {matching: 1},
+            JumpForwardIfZero {matching: None},
+            Write,
+            JumpBackwardUnlessZero {matching: 4},
+            JumpForwardIfZero {matching: None},
+            Read,
+            JumpBackwardUnlessZero {matching: 7},
+            Read,
+            Write,
+            Right(1),
+            Decrement(1),
+            Read,
+            Increment(1),
+        ]);
 
         // A program with some adjacent groups of instructions as well as some single, ungrouped
         // instructions
         // Some of these instructions like writes, reads and jumps are not meant to be grouped
         // This test also tests to make sure that they are left separate when adjacent in the input
+        #[allow(clippy::redundant_static_lifetimes)]
         const SOME_GROUPS: &'static [u8] = b"><>>>++-[[[<+,-.>]]]...,,,,++++---<<<>>>[>>.,]";
         // If there are groups, or even a mix of groups/non-groups, the output should be different
         test_precompile(SOME_GROUPS, OptimizationLevel::Off, vec![
@@ -161,11 +243,8 @@ mod tests {
             JumpBackwardUnlessZero {matching: 41},
         ]);
         test_precompile(SOME_GROUPS, OptimizationLevel::Speed, vec![
-            Right(1),
-            Left(1),
             Right(3),
-            Increment(2),
-            Decrement(1),
+            Increment(1),
             JumpForwardIfZero {matching: None},
             JumpForwardIfZero {matching: None},
             JumpForwardIfZero {matching: None},
@@ -175,9 +254,9 @@ mod tests {
             Decrement(1),
             Write,
             Right(1),
-            JumpBackwardUnlessZero {matching: 8},
-            JumpBackwardUnlessZero {matching: 7},
-            JumpBackwardUnlessZero {matching: 6},
+            JumpBackwardUnlessZero {matching: 5},
+            JumpBackwardUnlessZero {matching: 4},
+            JumpBackwardUnlessZero {matching: 3},
             Write,
             Write,
             Write,
@@ -185,15 +264,12 @@ mod tests {
             Read,
             Read,
             Read,
-            Increment(4),
-            Decrement(3),
-            Left(3),
-            Right(3),
+            Increment(1),
             JumpForwardIfZero {matching: None},
             Right(2),
             Write,
             Read,
-            JumpBackwardUnlessZero {matching: 29},
+            JumpBackwardUnlessZero {matching: 23},
         ]);
     }
 
@@ -219,15 +295,12 @@ mod tests {
         ", OptimizationLevel::Speed, vec![
             JumpForwardIfZero {matching: None},
             JumpBackwardUnlessZero {matching: 1},
-            Right(3),
-            Right(3),
-            Left(3),
-            Right(1),
+            Right(4),
             Increment(2),
             Left(1),
             Decrement(2),
             JumpForwardIfZero {matching: None},
-            JumpBackwardUnlessZero {matching: 10},
+            JumpBackwardUnlessZero {matching: 7},
             Write,
             Read,
             Read,
@@ -257,62 +330,144 @@ mod tests {
     }
 
     #[test]
-    #[should_panic(expected = "Mismatched jump instruction")]
-    fn mismatched_jumps1() {
-        precompile(b"[".iter(), OptimizationLevel::Off);
-    }
-    #[test]
-    #[should_panic(expected = "Mismatched jump instruction")]
-    fn mismatched_jumps2() {
-        precompile(b"]".iter(), OptimizationLevel::Off);
-    }
-    #[test]
-    #[should_panic(expected = "Mismatched jump instruction")]
-    fn mismatched_jumps3() {
-        precompile(b"][".iter(), OptimizationLevel::Off);
+    fn mismatched_jumps() {
+        use super::super::ParseErrorKind::*;
+
+        test_mismatched(b"[", &[(UnmatchedJumpForward, 0)]);
+        test_mismatched(b"]", &[(UnmatchedJumpBackward, 0)]);
+        test_mismatched(b"][", &[(UnmatchedJumpBackward, 0), (UnmatchedJumpForward, 1)]);
+        test_mismatched(b"][]", &[(UnmatchedJumpBackward, 0)]);
+        test_mismatched(b"][][", &[(UnmatchedJumpBackward, 0), (UnmatchedJumpForward, 3)]);
+        test_mismatched(b"[]][]", &[(UnmatchedJumpBackward, 2)]);
+        test_mismatched(b"[][[]", &[(UnmatchedJumpForward, 2)]);
+        test_mismatched(b"[][[]]]", &[(UnmatchedJumpBackward, 6)]);
+        test_mismatched(b"[[[[[[[[]]][][[[]]]]][[[]]]][[[[]]]]]", &[(UnmatchedJumpForward, 0)]);
+        test_mismatched(b"[][]]", &[(UnmatchedJumpBackward, 4)]);
+        test_mismatched(b"]]>[[", &[
+            (UnmatchedJumpBackward, 0),
+            (UnmatchedJumpBackward, 1),
+            (UnmatchedJumpForward, 3),
+            (UnmatchedJumpForward, 4),
+        ]);
     }
+
     #[test]
-    #[should_panic(expected = "Mismatched jump instruction")]
-    fn mismatched_jumps4() {
-        precompile(b"][]".iter(), OptimizationLevel::Off);
+    fn mismatched_jumps_grouped() {
+        // Errors should be the same regardless of the optimization level
+        let err = precompile(b"[[[+++".iter(), OptimizationLevel::Speed).unwrap_err();
+        let offsets: Vec<_> = err.unmatched.iter().map(|jump| jump.position.offset).collect();
+        assert_eq!(offsets, vec![0, 1, 2]);
     }
+
     #[test]
-    #[should_panic(expected = "Mismatched jump instruction")]
-    fn mismatched_jumps5() {
-        precompile(b"][][".iter(), OptimizationLevel::Off);
+    fn mismatched_jump_position() {
+        let err = precompile(b"+[\n>>  ]\n\t ]-".iter(), OptimizationLevel::Off).unwrap_err();
+        assert_eq!(err.kind(), ParseErrorKind::UnmatchedJumpBackward);
+        assert_eq!(err.position(), SourcePosition {offset: 11, line: 3, column: 3});
+        assert_eq!(err.unmatched.len(), 1);
     }
+
     #[test]
-    #[should_panic(expected = "Mismatched jump instruction")]
-    fn mismatched_jumps6() {
-        precompile(b"[]][]".iter(), OptimizationLevel::Off);
+    fn clear_loops() {
+        const CLEARS: &[u8] = b"+++[-]>[+]++<[-]-.";
+        test_precompile(CLEARS, OptimizationLevel::Off, vec![
+            Increment(1),
+            Increment(1),
+            Increment(1),
+            JumpForwardIfZero {matching: None},
+            Decrement(1),
+            JumpBackwardUnlessZero {matching: 4},
+            Right(1),
+            JumpForwardIfZero {matching: None},
+            Increment(1),
+            JumpBackwardUnlessZero {matching: 8},
+            Increment(1),
+            Increment(1),
+            Left(1),
+            JumpForwardIfZero {matching: None},
+            Decrement(1),
+            JumpBackwardUnlessZero {matching: 14},
+            Decrement(1),
+            Write,
+        ]);
+        test_precompile(CLEARS, OptimizationLevel::Speed, vec![
+            Increment(3),
+            Set(0),
+            Right(1),
+            Set(2),
+            Left(1),
+            Set(-1),
+            Write,
+        ]);
     }
+
     #[test]
-    #[should_panic(expected = "Mismatched jump instruction")]
-    fn mismatched_jumps7() {
-        precompile(b"[][[]".iter(), OptimizationLevel::Off);
+    fn multiply_loops() {
+        test_precompile(b"++[->+++>-<<]>.", OptimizationLevel::Aggressive, vec![
+            Increment(2),
+            MulAdd {offset: 1, factor: 3},
+            MulAdd {offset: 2, factor: -1},
+            Set(0),
+            Right(1),
+            Write,
+        ]);
     }
+
     #[test]
-    #[should_panic(expected = "Mismatched jump instruction")]
-    fn mismatched_jumps8() {
-        precompile(b"[][[]".iter(), OptimizationLevel::Off);
+    fn scan_loops() {
+        test_precompile(b"+[>>>]<[<]", OptimizationLevel::Aggressive, vec![
+            Increment(1),
+            ScanRight(3),
+            Left(1),
+            ScanLeft(1),
+        ]);
     }
+
     #[test]
-    #[should_panic(expected = "Mismatched jump instruction")]
-    fn mismatched_jumps9() {
-        precompile(b"[][[]]]".iter(), OptimizationLevel::Off);
+    fn offset_addressing() {
+        test_precompile(b">+>++<<-.>>[-]", OptimizationLevel::Maximum, vec![
+            AddAt {offset: 1, delta: 1},
+            AddAt {offset: 2, delta: 2},
+            Decrement(1),
+            Write,
+            Right(2),
+            Set(0),
+        ]);
     }
+
     #[test]
-    #[should_panic(expected = "Mismatched jump instruction")]
-    fn mismatched_jumps10() {
-        precompile(b"[[[[[[[[]]][][[[]]]]][[[]]]][[[[]]]]]".iter(), OptimizationLevel::Off);
+    fn source_map() {
+        let source = b"+[\n>>>>> ;-]\n..";
+        let (instructions, map) = precompile_with_source_map(source.iter(), OptimizationLevel::Speed).unwrap();
+        assert_eq!(instructions.len(), map.len());
+        let spans: Vec<_> = map.iter().map(|span| (span.start.offset, span.len, span.start.line, span.start.column)).collect();
+        assert_eq!(spans, vec![
+            (0, 1, 1, 1),
+            (1, 1, 1, 2),
+            (3, 5, 2, 1),
+            (10, 1, 2, 8),
+            (11, 1, 2, 9),
+            (13, 1, 3, 1),
+            (14, 1, 3, 2),
+        ]);
+
+        // Without optimizations, every instruction maps to exactly one byte
+        let (instructions, map) = precompile_with_source_map(source.iter(), OptimizationLevel::Off).unwrap();
+        assert_eq!(instructions.len(), map.len());
+        assert!(map.iter().all(|span| span.len == 1));
+        assert_eq!(map.get(6).unwrap().start, SourcePosition {offset: 7, line: 2, column: 5});
+        assert_eq!(map.get(instructions.len()), None);
     }
-    #[test]
-    #[should_panic(expected = "Mismatched jump instruction")]
-    fn mismatched_jumps11() {
-        precompile(b"[][]]".iter(), OptimizationLevel::Off);
+
+    fn test_mismatched(input: &[u8], expected: &[(ParseErrorKind, usize)]) {
+        let err = precompile(input.iter(), OptimizationLevel::Off).unwrap_err();
+        let actual: Vec<_> = err.unmatched.iter()
+            .map(|jump| (jump.kind, jump.position.offset))
+            .collect();
+        assert_eq!(actual, expected, "wrong errors for {:?}", String::from_utf8_lossy(input));
     }
 
     fn test_precompile(input: &[u8], opt: OptimizationLevel, expected: Vec<Instruction>) {
-        assert_eq!(precompile(input.iter(), opt), expected);
+        assert_eq!(precompile(input.iter(), opt).unwrap(), expected);
     }
 }
@@ -0,0 +1,70 @@
+use std::fmt;
+use std::io;
+use std::error::Error;
+
+use super::ExecutionLimit;
+
+/// Returned when the interpreter could not finish running a program
+#[derive(Debug)]
+pub enum RuntimeError {
+    /// Writing the output of a "." instruction failed
+    OutputError(io::Error),
+    /// Reading the input of a "," instruction failed
+    /// Reaching the end of the input is not an error
+    InputError(io::Error),
+    /// The "[" instruction at the given index has no matching "]"
+    MismatchedJump {
+        instruction: usize,
+    },
+    /// The "," instruction at the given index tried to read past the end of the input while
+    /// using `EofPolicy::Error`
+    EndOfInput {
+        instruction: usize,
+    },
+    /// The instruction at the given index moved the pointer past the end of the tape while
+    /// using `TapeBoundary::Error`, or needed a growing tape to have more cells than its size
+    OutOfBounds {
+        instruction: usize,
+    },
+    /// The program ran past one of the limits placed on its execution and was stopped before
+    /// it could run the instruction at the given index
+    LimitExceeded {
+        limit: ExecutionLimit,
+        instruction: usize,
+        /// The position of the pointer in the memory buffer (see `InterpreterState`)
+        pointer: usize,
+        /// The number of instructions that were run
+        steps: u64,
+    },
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RuntimeError::OutputError(ref err) => write!(f, "Could not write output: {}", err),
+            RuntimeError::InputError(ref err) => write!(f, "Could not read input: {}", err),
+            RuntimeError::MismatchedJump {instruction} => {
+                write!(f, "Mismatched `[` instruction at instruction {}", instruction)
+            },
+            RuntimeError::EndOfInput {instruction} => {
+                write!(f, "Reached the end of the input at instruction {}", instruction)
+            },
+            RuntimeError::OutOfBounds {instruction} => {
+                write!(f, "Instruction {} moved the pointer past the end of the tape", instruction)
+            },
+            RuntimeError::LimitExceeded {limit, instruction, pointer, steps} => {
+                write!(f, "Exceeded the limit of {} at instruction {} with the pointer at {} after {} steps",
+                    limit, instruction, pointer, steps)
+            },
+        }
+    }
+}
+
+impl Error for RuntimeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            RuntimeError::OutputError(ref err) | RuntimeError::InputError(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,76 @@
+use std::slice;
+
+use super::SourcePosition;
+
+/// A contiguous range of bytes in the source of a brainfuck program
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Span {
+    /// The position of the first byte in the range
+    pub start: SourcePosition,
+    /// The number of bytes in the range
+    pub len: usize,
+}
+
+impl Span {
+    /// The offset of the byte just past the end of this span
+    pub fn end(&self) -> usize {
+        self.start.offset + self.len
+    }
+
+    /// Returns the smallest span that covers both this span and the other span
+    pub fn merge(&self, other: &Span) -> Span {
+        let start = if other.start.offset < self.start.offset { other.start } else { self.start };
+        let end = if other.end() > self.end() { other.end() } else { self.end() };
+        Span {start, len: end - start.offset}
+    }
+}
+
+/// Maps each instruction of a precompiled program back to the source it came from
+///
+/// Since optimizations can group several bytes of source into a single instruction, each
+/// instruction maps to an entire span of bytes rather than a single position.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct SourceMap {
+    spans: Vec<Span>,
+}
+
+impl SourceMap {
+    pub fn new(spans: Vec<Span>) -> SourceMap {
+        SourceMap {spans}
+    }
+
+    /// Returns the span of source that produced the instruction at the given index
+    pub fn get(&self, instruction: usize) -> Option<Span> {
+        self.spans.get(instruction).cloned()
+    }
+
+    /// The number of instructions in the map
+    pub fn len(&self) -> usize {
+        self.spans.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.spans.is_empty()
+    }
+
+    /// Iterate over the spans of every instruction in order
+    pub fn iter(&self) -> slice::Iter<'_, Span> {
+        self.spans.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge() {
+        let a = Span {start: SourcePosition {offset: 2, line: 1, column: 3}, len: 3};
+        let b = Span {start: SourcePosition {offset: 7, line: 2, column: 1}, len: 2};
+        let merged = Span {start: a.start, len: 7};
+        assert_eq!(a.merge(&b), merged);
+        assert_eq!(b.merge(&a), merged);
+        assert_eq!(merged.end(), 9);
+        assert_eq!(a.merge(&a), a);
+    }
+}
@@ -0,0 +1,30 @@
+use std::fmt;
+
+/// A location in the source of a brainfuck program
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct SourcePosition {
+    /// The index of the byte in the source
+    pub offset: usize,
+    /// The line number of the byte (starting at 1)
+    pub line: usize,
+    /// The column of the byte within its line (starting at 1)
+    /// Columns are counted in bytes, not characters
+    pub column: usize,
+}
+
+impl fmt::Display for SourcePosition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display() {
+        let pos = SourcePosition {offset: 14, line: 3, column: 2};
+        assert_eq!(pos.to_string(), "line 3, column 2");
+    }
+}
@@ -0,0 +1,86 @@
+use std::str::FromStr;
+
+/// The number of cells in a tape with a fixed size when no size is given
+pub const DEFAULT_TAPE_SIZE: usize = 30000;
+
+/// What happens when the pointer moves past either end of the tape
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum TapeBoundary {
+    /// Add cells to that end of the tape. The tape starts with a single cell.
+    #[default]
+    Grow,
+    /// Move the pointer around to the other end of the tape
+    Wrap,
+    /// Stop running the program with `RuntimeError::OutOfBounds`
+    ///
+    /// Only the movements left after optimizing are checked. For example, `<>` at the start of
+    /// the tape fails when it is not optimized, but optimizing cancels it out entirely so
+    /// nothing fails. Precompile with `OptimizationLevel::Off` to check every single movement.
+    Error,
+    /// Leave the pointer at the end of the tape
+    ///
+    /// Every optimization level, including folding runs at level 1, assumes that moving away
+    /// from a cell and back returns to the same cell, which is not true at the end of a
+    /// clamped tape. Programs run on a clamped tape must be precompiled with
+    /// `OptimizationLevel::Off` (see `allows_optimization`).
+    Clamp,
+}
+
+impl TapeBoundary {
+    /// Returns true if an optimized program behaves the same on this kind of tape as the
+    /// program precompiled with `OptimizationLevel::Off`
+    pub fn allows_optimization(self) -> bool {
+        self != TapeBoundary::Clamp
+    }
+}
+
+impl FromStr for TapeBoundary {
+    type Err = ();
+
+    fn from_str(val: &str) -> Result<Self, Self::Err> {
+        match val {
+            "grow" => Ok(TapeBoundary::Grow),
+            "wrap" => Ok(TapeBoundary::Wrap),
+            "error" => Ok(TapeBoundary::Error),
+            "clamp" => Ok(TapeBoundary::Clamp),
+            _ => Err(()),
+        }
+    }
+}
+
+/// The size of the tape and what happens at its ends
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct TapeConfig {
+    /// The most cells the tape may have, or None for no limit
+    ///
+    /// A tape that grows stops the program with `RuntimeError::OutOfBounds` if it would need
+    /// more cells than this. Every other kind of tape has exactly this many cells (or
+    /// `DEFAULT_TAPE_SIZE` cells if there is no limit) and the pointer starts at its first
+    /// cell. A size of zero is treated as a size of one.
+    pub size: Option<usize>,
+    /// What happens when the pointer moves past either end of the tape
+    pub boundary: TapeBoundary,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_str() {
+        assert_eq!("grow".parse(), Ok(TapeBoundary::Grow));
+        assert_eq!("wrap".parse(), Ok(TapeBoundary::Wrap));
+        assert_eq!("error".parse(), Ok(TapeBoundary::Error));
+        assert_eq!("clamp".parse(), Ok(TapeBoundary::Clamp));
+
+        assert!("foo".parse::<TapeBoundary>().is_err());
+    }
+
+    #[test]
+    fn allows_optimization() {
+        assert!(TapeBoundary::Grow.allows_optimization());
+        assert!(TapeBoundary::Wrap.allows_optimization());
+        assert!(TapeBoundary::Error.allows_optimization());
+        assert!(!TapeBoundary::Clamp.allows_optimization());
+    }
+}
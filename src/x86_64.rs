@@ -0,0 +1,275 @@
+//! A minimal x86-64 assembler for the instructions needed to compile brainfuck programs
+
+// Some registers and instructions are only needed by the JIT
+#![cfg_attr(not(feature = "jit"), allow(dead_code))]
+
+// Registers
+pub const RAX: u8 = 0;
+pub const RCX: u8 = 1;
+pub const RDX: u8 = 2;
+pub const RBX: u8 = 3;
+pub const RSI: u8 = 6;
+pub const RDI: u8 = 7;
+pub const R12: u8 = 12;
+pub const R13: u8 = 13;
+pub const R14: u8 = 14;
+pub const R15: u8 = 15;
+
+// Condition codes for jumps
+pub const BELOW: u8 = 0x2;
+pub const ABOVE_OR_EQUAL: u8 = 0x3;
+pub const EQUAL: u8 = 0x4;
+pub const NOT_EQUAL: u8 = 0x5;
+pub const BELOW_OR_EQUAL: u8 = 0x6;
+pub const ABOVE: u8 = 0x7;
+pub const SIGN: u8 = 0x8;
+
+/// Operand that refers to a cell in memory
+#[derive(Clone, Copy)]
+pub enum Cell {
+    // [rbx + disp]
+    Rbx(i32),
+    // [rcx] with the address already computed
+    Rcx,
+}
+
+/// Emits x86-64 machine code
+///
+/// Labels are indexes into a table of code offsets. The first labels can be reserved (e.g. for
+/// the instructions of a program so that jumps can refer to them by instruction index).
+///
+/// Instructions that operate on cells expect the address of the current cell to be in RBX.
+pub struct Assembler {
+    code: Vec<u8>,
+    labels: Vec<Option<usize>>,
+    // offsets of rel32 values that need to be filled in with the address of a label
+    fixups: Vec<(usize, usize)>,
+}
+
+impl Assembler {
+    pub fn new(reserved_labels: usize) -> Assembler {
+        Assembler {
+            code: Vec::new(),
+            labels: vec![None; reserved_labels],
+            fixups: Vec::new(),
+        }
+    }
+
+    pub fn new_label(&mut self) -> usize {
+        self.labels.push(None);
+        self.labels.len() - 1
+    }
+
+    pub fn bind(&mut self, label: usize) {
+        debug_assert!(self.labels[label].is_none(), "bug: label bound twice");
+        self.labels[label] = Some(self.code.len());
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        for &(at, label) in &self.fixups {
+            let target = self.labels[label].expect("bug: jump to label that was never bound");
+            let rel = target as i64 - (at as i64 + 4);
+            self.code[at..at + 4].copy_from_slice(&(rel as i32).to_le_bytes());
+        }
+        self.code
+    }
+
+    pub fn emit(&mut self, bytes: &[u8]) {
+        self.code.extend_from_slice(bytes);
+    }
+
+    pub fn rel32(&mut self, label: usize) {
+        self.fixups.push((self.code.len(), label));
+        self.emit(&[0; 4]);
+    }
+
+    /// Emits a REX prefix if one is needed
+    pub fn rex(&mut self, wide: bool, reg: u8, rm: u8) {
+        let rex = 0x40 | (wide as u8) << 3 | (reg >> 3) << 2 | (rm >> 3);
+        if rex != 0x40 {
+            self.emit(&[rex]);
+        }
+    }
+
+    /// Emits a ModRM byte for two registers
+    pub fn modrm_reg(&mut self, reg: u8, rm: u8) {
+        self.emit(&[0xC0 | (reg & 7) << 3 | (rm & 7)]);
+    }
+
+    /// Emits the ModRM byte and displacement for a memory operand
+    pub fn modrm_cell(&mut self, reg: u8, cell: Cell) {
+        let reg = (reg & 7) << 3;
+        match cell {
+            Cell::Rbx(0) => self.emit(&[reg | RBX]),
+            Cell::Rbx(disp) if disp as i8 as i32 == disp => self.emit(&[0x40 | reg | RBX, disp as u8]),
+            Cell::Rbx(disp) => {
+                self.emit(&[0x80 | reg | RBX]);
+                self.emit(&disp.to_le_bytes());
+            },
+            Cell::Rcx => self.emit(&[reg | RCX]),
+        }
+    }
+
+    /// Returns an operand for the cell at the given offset from the pointer, computing its
+    /// address into RCX first if the offset is too large to encode
+    pub fn cell(&mut self, offset: isize) -> Cell {
+        if offset as i32 as isize == offset {
+            Cell::Rbx(offset as i32)
+        }
+        else {
+            self.mov_imm(RCX, offset as u64);
+            // add rcx, rbx
+            self.rex(true, RBX, RCX);
+            self.emit(&[0x01]);
+            self.modrm_reg(RBX, RCX);
+            Cell::Rcx
+        }
+    }
+
+    pub fn ret(&mut self) {
+        self.emit(&[0xC3]);
+    }
+
+    /// mov dst, src
+    pub fn mov_reg(&mut self, dst: u8, src: u8) {
+        self.rex(true, src, dst);
+        self.emit(&[0x89]);
+        self.modrm_reg(src, dst);
+    }
+
+    /// mov dst, imm64
+    pub fn mov_imm(&mut self, dst: u8, value: u64) {
+        self.rex(true, 0, dst);
+        self.emit(&[0xB8 | (dst & 7)]);
+        self.emit(&value.to_le_bytes());
+    }
+
+    /// xor dst, dst
+    pub fn xor_reg(&mut self, dst: u8) {
+        self.rex(false, dst, dst);
+        self.emit(&[0x31]);
+        self.modrm_reg(dst, dst);
+    }
+
+    /// cmp a, b
+    pub fn cmp_reg(&mut self, a: u8, b: u8) {
+        self.rex(true, b, a);
+        self.emit(&[0x39]);
+        self.modrm_reg(b, a);
+    }
+
+    /// add rbx, amount
+    pub fn add_rbx(&mut self, amount: isize) {
+        if amount as i32 as isize == amount {
+            self.emit(&[0x48, 0x81, 0xC3]);
+            self.emit(&(amount as i32).to_le_bytes());
+        }
+        else {
+            self.mov_imm(RAX, amount as u64);
+            self.rex(true, RAX, RBX);
+            self.emit(&[0x01]);
+            self.modrm_reg(RAX, RBX);
+        }
+    }
+
+    /// cmp byte [rbx], 0
+    pub fn cmp_cell_zero(&mut self) {
+        self.emit(&[0x80]);
+        self.modrm_cell(7, Cell::Rbx(0));
+        self.emit(&[0x00]);
+    }
+
+    /// add byte [cell], value
+    pub fn add_cell(&mut self, offset: isize, value: u8) {
+        let cell = self.cell(offset);
+        self.emit(&[0x80]);
+        self.modrm_cell(0, cell);
+        self.emit(&[value]);
+    }
+
+    /// mov byte [cell], value
+    pub fn set_cell(&mut self, offset: isize, value: u8) {
+        let cell = self.cell(offset);
+        self.emit(&[0xC6]);
+        self.modrm_cell(0, cell);
+        self.emit(&[value]);
+    }
+
+    /// movzx dst, byte [cell]
+    pub fn load_cell(&mut self, dst: u8, offset: isize) {
+        let cell = self.cell(offset);
+        self.emit(&[0x0F, 0xB6]);
+        self.modrm_cell(dst, cell);
+    }
+
+    /// lea dst, [cell]
+    pub fn lea_cell(&mut self, dst: u8, offset: isize) {
+        let cell = self.cell(offset);
+        self.rex(true, dst, 0);
+        self.emit(&[0x8D]);
+        self.modrm_cell(dst, cell);
+    }
+
+    /// Adds the value in AL multiplied by factor to the cell
+    pub fn mul_add_cell(&mut self, offset: isize, factor: u8) {
+        match factor {
+            1 => {},
+            // Subtracting is the same as adding the value multiplied by -1
+            255 => {},
+            _ => {
+                // imul eax, eax, factor
+                self.emit(&[0x69, 0xC0]);
+                self.emit(&(factor as i32).to_le_bytes());
+            },
+        }
+        let cell = self.cell(offset);
+        // add byte [cell], al or sub byte [cell], al
+        self.emit(&[if factor == 255 { 0x28 } else { 0x00 }]);
+        self.modrm_cell(RAX, cell);
+    }
+
+    /// Jumps to the label if the condition holds
+    pub fn jcc(&mut self, condition: u8, label: usize) {
+        self.emit(&[0x0F, 0x80 | condition]);
+        self.rel32(label);
+    }
+
+    pub fn jmp(&mut self, label: usize) {
+        self.emit(&[0xE9]);
+        self.rel32(label);
+    }
+
+    /// Calls a subroutine in the generated code
+    pub fn call(&mut self, label: usize) {
+        self.emit(&[0xE8]);
+        self.rel32(label);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encoding() {
+        let mut asm = Assembler::new(0);
+        let label = asm.new_label();
+        asm.bind(label);
+        asm.mov_reg(R12, RDI);
+        asm.cmp_reg(RBX, R14);
+        asm.add_cell(-1, 3);
+        asm.load_cell(RSI, 200);
+        asm.xor_reg(R15);
+        asm.jcc(NOT_EQUAL, label);
+        asm.ret();
+        assert_eq!(asm.finish(), vec![
+            0x49, 0x89, 0xFC,
+            0x4C, 0x39, 0xF3,
+            0x80, 0x43, 0xFF, 0x03,
+            0x0F, 0xB6, 0xB3, 0xC8, 0x00, 0x00, 0x00,
+            0x45, 0x31, 0xFF,
+            0x0F, 0x85, 0xE6, 0xFF, 0xFF, 0xFF,
+            0xC3,
+        ]);
+    }
+}